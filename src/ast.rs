@@ -1,6 +1,15 @@
 use itertools::Itertools;
 use std::fmt::{Display, Error, Formatter};
-use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use bigint::BigInt;
+use decimal::Decimal;
+use compiler::Chunk;
+use env::Closure;
+use limits;
 
 #[derive(Debug, PartialEq)]
 pub enum Line {
@@ -10,17 +19,119 @@ pub enum Line {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    DeclareVar(String, Expr),
-    MutateVar(AssignOp, String, Expr),
+    DeclareVar(Pattern, Expr),
+    DeclareConst(Pattern, Expr, usize), // (pattern, value, line)
+    MutateVar(AssignOp, Vec<Var>, Vec<Expr>), // `a, b = b, a;` assigns in parallel
     Expression(Expr),
     Return(Expr),
     If(IfStatement, Option<Vec<IfStatement>>, Option<Vec<Statement>>), // (If, Else If, Else)
-    While(Expr, Vec<Statement>),
+    While(Expr, Vec<Statement>, Range<usize>), // (condition, body, the hoisted expressions it forgets each time it starts)
     Print(Vec<Expr>),
     Println(Vec<Expr>),
+    EPrint(Vec<Expr>),
+    EPrintln(Vec<Expr>),
     Exit(Expr),
 }
 
+/// What `let` binds: a name, or `[a, [b, c], ...rest]` to take an array apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Name(Var),
+    Array(Vec<Pattern>),
+    Rest(Var), // `...name`, only allowed inside an array pattern
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            Pattern::Name(ref var) => write!(f, "{}", var.name),
+            Pattern::Array(ref items) => {
+                write!(f, "[{}]", items.iter().format_with(", ", |item, f| f(&format_args!("{}", item))))
+            },
+            Pattern::Rest(ref var) => write!(f, "...{}", var.name),
+        }
+    }
+}
+
+/// A variable named in the source, and where the resolver found it.
+#[derive(Debug, Clone)]
+pub struct Var {
+    pub name: String,
+    pub slot: Option<Slot>, // `None` until it's resolved, and for a builtin function
+}
+
+impl Var {
+    pub fn new(name: String) -> Var {
+        Var {
+            name: name,
+            slot: None,
+        }
+    }
+}
+
+impl PartialEq for Var {
+    fn eq(&self, other: &Var) -> bool {
+        self.name == other.name
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    Local(usize), // Of the function that's running
+    Global(usize),
+}
+
+/// The parameters and body of a function, shared by every value made from the same definition.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: Option<String>, // The variable it was declared as, for errors
+    pub params: Vec<Param>,
+    pub body: Vec<Statement>,
+    pub locals: Vec<String>, // The parameters, then every other variable the body or a function in it uses
+    pub captures: Vec<Slot>, // Where each local is, in the code the function is defined in
+    pub code: OnceCell<Rc<Chunk>>, // Compiled the first time the bytecode engine calls it
+}
+
+impl Function {
+    pub fn new(params: Vec<Param>, body: Vec<Statement>) -> Function {
+        Function {
+            name: None,
+            params: params,
+            body: body,
+            locals: Vec::new(),
+            captures: Vec::new(),
+            code: OnceCell::new(),
+        }
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        self.params == other.params && self.body == other.body
+    }
+}
+
+/// A function parameter: `name: type`, `name: type = default` or `...name: array`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub t: Type,
+    pub default: Option<Expr>, // Evaluated on each call that doesn't pass the argument
+    pub variadic: bool,
+}
+
+impl Param {
+    pub fn param_type(&self) -> ParamType {
+        if self.variadic {
+            ParamType::Variadic(self.t.clone())
+        } else if self.default.is_some() {
+            ParamType::Optional(self.t.clone())
+        } else {
+            ParamType::Required(self.t.clone())
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct IfStatement {
     pub e: Expr,
@@ -31,11 +142,12 @@ pub struct IfStatement {
 pub enum Expr {
     Literal(Value),
     FuncDef(Value),
-    Reference(String),
+    Reference(Var),
     Typecast(Box<Expr>, Box<Expr>),
     TypeOf(Box<Expr>),
-    CallFunc(Box<Expr>, Vec<Expr>),
+    CallFunc(Box<Expr>, Vec<Expr>, Vec<(String, Expr)>), // (func, positional, named)
     Array(Vec<Expr>),
+    Dict(Vec<(String, Expr)>),
     Index(Box<Expr>, Box<Expr>),
     BinOp(Op, Box<Expr>, Box<Expr>),
     Comparison(CompOp, Box<Expr>, Box<Expr>),
@@ -44,9 +156,10 @@ pub enum Expr {
     Length(Box<Expr>),
     ToUpper(Box<Expr>),
     ToLower(Box<Expr>),
+    Hoisted(usize, Box<Expr>), // Added by the optimizer: worked out once each time the loop it's in runs
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Op {
     Add,
     Sub,
@@ -54,9 +167,36 @@ pub enum Op {
     Div,
     Mod,
     Exp,
+    FloorDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let text = match *self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Exp => "**",
+            Op::FloorDiv => "//",
+            Op::BitAnd => "&",
+            Op::BitOr => "|",
+            Op::BitXor => "^",
+            Op::Shl => "<<",
+            Op::Shr => ">>",
+        };
+
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AssignOp {
     Equals,
     AddEq,
@@ -65,9 +205,15 @@ pub enum AssignOp {
     DivEq,
     ModEq,
     ExpEq,
+    FloorDivEq,
+    BitAndEq,
+    BitOrEq,
+    BitXorEq,
+    ShlEq,
+    ShrEq,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CompOp {
     Equal,
     NotEq,
@@ -77,35 +223,61 @@ pub enum CompOp {
     Le,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BoolLogic {
     And,
     Or,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnaryOp {
     Not,
+    Neg,
+    BitNot,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Num,
+    Int,
+    Decimal,
     String,
     Boolean,
     Array,
+    Dict,
     Type,
     Void,
-    Func(Vec<Type>),
+    Func(Vec<ParamType>),
+}
+
+/// A parameter in a function type, shown as `num`, `string?` (has a default) or `...array`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamType {
+    Required(Type),
+    Optional(Type),
+    Variadic(Type),
+}
+
+impl Display for ParamType {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            ParamType::Required(ref t) => write!(f, "{}", t),
+            ParamType::Optional(ref t) => write!(f, "{}?", t),
+            ParamType::Variadic(ref t) => write!(f, "...{}", t),
+        }
+    }
 }
 
 impl Display for Type {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         let text = match *self {
             Type::Num => "num".to_string(),
+            Type::Int => "int".to_string(),
+            Type::Decimal => "decimal".to_string(),
             Type::String => "string".to_string(),
             Type::Boolean => "bool".to_string(),
             Type::Array => "array".to_string(),
+            Type::Dict => "dict".to_string(),
             Type::Type => "type".to_string(),
             Type::Void => "void".to_string(),
             Type::Func(ref args) => {
@@ -120,24 +292,61 @@ impl Display for Type {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Num(f64),
-    String(String),
+    Int(i64),
+    BigInt(BigInt), // An int too big for an i64
+    Decimal(Decimal),
+    String(Rc<String>), // Shared, and only copied when something changes it
     Boolean(bool),
-    Array(Vec<Value>),
+    Array(Rc<Vec<Value>>), // Likewise
+    Dict(BTreeMap<String, Value>),
     Type(Type),
     Void,
-    Func(Option<HashMap<String, Value>>, Vec<(String, Type)>, Vec<Statement>), // (env, args, body)
+    Func(Option<Rc<Closure>>, Rc<Function>), // (variables it was defined with, definition)
 }
 
+// New strings, arrays and dicts are made with these, so they count against the limits.
 impl Value {
+    pub fn string<S: Into<String>>(s: S) -> Value {
+        let value = Value::String(Rc::new(s.into()));
+        limits::track(&value);
+        value
+    }
+
+    pub fn array(items: Vec<Value>) -> Value {
+        let value = Value::Array(Rc::new(items));
+        limits::track(&value);
+        value
+    }
+
+    pub fn dict(entries: BTreeMap<String, Value>) -> Value {
+        let value = Value::Dict(entries);
+        limits::track(&value);
+        value
+    }
+
     pub fn get_type(&self) -> Type {
         match *self {
             Value::Num(_) => Type::Num,
+            Value::Int(_) | Value::BigInt(_) => Type::Int,
+            Value::Decimal(_) => Type::Decimal,
             Value::String(_) => Type::String,
             Value::Boolean(_) => Type::Boolean,
             Value::Array(_) => Type::Array,
+            Value::Dict(_) => Type::Dict,
             Value::Type(_) => Type::Type,
             Value::Void => Type::Void,
-            Value::Func(ref _env, ref args, ref _body) => Type::Func(args.iter().map(|a| a.1.clone()).collect()),
+            Value::Func(_, ref func) => Type::Func(func.params.iter().map(Param::param_type).collect()),
+        }
+    }
+
+    /// The value of a number as a floating point number.
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            Value::Num(n) => Some(n),
+            Value::Int(i) => Some(i as f64),
+            Value::BigInt(ref b) => Some(b.to_f64()),
+            Value::Decimal(ref d) => Some(d.to_f64()),
+            _ => None,
         }
     }
 }
@@ -146,18 +355,22 @@ impl Display for Value {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         let text = match *self {
             Value::Num(num) => num.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::BigInt(ref b) => b.to_string(),
+            Value::Decimal(ref d) => d.to_string(),
             Value::String(ref string) => string.to_string(),
             Value::Boolean(b) => b.to_string(),
             Value::Array(ref vec) => {
                 let list = vec.iter().format_with(", ", |item, f| f(&format_args!("{}", item)));
                 format!("[{}]", list)
             },
+            Value::Dict(ref map) => {
+                let list = map.iter().format_with(", ", |(k, v), f| f(&format_args!("{}: {}", k, v)));
+                format!("{{{}}}", list)
+            },
             Value::Type(ref t) => t.to_string(),
             Value::Void => "void".to_string(),
-            Value::Func(ref _env, ref args, ref _body) => {
-                let list = args.iter().format_with(", ", |item, f| f(&format_args!("{}", item.1)));
-                format!("func({})", list)
-            },
+            Value::Func(..) => self.get_type().to_string(),
         };
 
         write!(f, "{}", text)
@@ -172,8 +385,11 @@ impl Ident {
               "true"
             | "false"
             | "let"
+            | "const"
             | "print"
             | "println"
+            | "eprint"
+            | "eprintln"
             | "while"
             | "if"
             | "elif"
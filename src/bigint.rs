@@ -0,0 +1,415 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::mem::size_of;
+
+use limits;
+
+// Arbitrary-precision integers, used when int arithmetic overflows. The
+// magnitude is stored as base 2^32 digits, least significant first, without
+// trailing zeros; zero is an empty magnitude and is never negative.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+// Results with more bits than this are an error rather than an attempt to
+// allocate all of memory.
+const MAX_BITS: u64 = 1 << 24;
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &digit) in long.iter().enumerate() {
+        let sum = digit as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+// `a - b`, where `a` is at least as large as `b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in a.iter().enumerate() {
+        let mut diff = digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = if diff < 0 { 1 } else { 0 };
+        if diff < 0 {
+            diff += 1 << 32;
+        }
+        result.push(diff as u32);
+    }
+    trim(result)
+}
+
+// Multiplying numbers near the size limit takes seconds, so this checks the
+// time limit on the way.
+fn mul_mag(a: &[u32], b: &[u32]) -> Result<Vec<u32>, String> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        if i % 256 == 255 {
+            limits::check_time()?;
+        }
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    Ok(trim(result))
+}
+
+// Division by a single digit, returning the quotient and remainder.
+fn div_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        quotient[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    (trim(quotient), rem as u32)
+}
+
+// Binary long division: slow for huge numbers, but short and obviously right.
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let (q, r) = div_small(a, b[0]);
+        return (q, trim(vec![r]));
+    }
+    if cmp_mag(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = Vec::new();
+    for bit in (0..a.len() * 32).rev() {
+        // rem = rem * 2 + next bit of a
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for digit in rem.iter_mut() {
+            let next = *digit >> 31;
+            *digit = (*digit << 1) | carry;
+            carry = next;
+        }
+        if carry > 0 {
+            rem.push(carry);
+        }
+
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (trim(quotient), rem)
+}
+
+impl BigInt {
+    fn new(negative: bool, magnitude: Vec<u32>) -> BigInt {
+        let magnitude = trim(magnitude);
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude: magnitude,
+        }
+    }
+
+    pub fn from_i64(n: i64) -> BigInt {
+        let abs = n.unsigned_abs();
+        BigInt::new(n < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+
+    /// The value as an i64, if it fits in one.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let abs = self.magnitude.iter().rev().fold(0u64, |acc, &d| (acc << 32) | d as u64);
+        if self.negative {
+            if abs <= i64::MAX as u64 + 1 {
+                Some((abs as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else if abs <= i64::MAX as u64 {
+            Some(abs as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Going through the decimal representation rounds correctly
+        self.to_string().parse().unwrap()
+    }
+
+    /// Parses an optionally signed string of decimal digits.
+    pub fn parse(s: &str) -> Option<BigInt> {
+        BigInt::parse_radix(s, 10)
+    }
+
+    pub fn parse_radix(s: &str, radix: u32) -> Option<BigInt> {
+        let (negative, digits) = match s.chars().next() {
+            Some('-') => (true, &s[1..]),
+            Some('+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
+        }
+
+        let mut magnitude = Vec::new();
+        for c in digits.chars() {
+            // A single digit first is a single row, which never checks the time
+            magnitude = mul_mag(&[radix], &magnitude).unwrap();
+            magnitude = add_mag(&magnitude, &[c.to_digit(radix).unwrap()]);
+        }
+        Some(BigInt::new(negative, magnitude))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::new(false, self.magnitude.clone())
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+
+    /// How many bytes the digits take up.
+    pub fn size(&self) -> usize {
+        self.magnitude.len() * size_of::<u32>()
+    }
+
+    /// How many bits the magnitude takes.
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(top) => self.magnitude.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_mag(&self.magnitude, &other.magnitude));
+        }
+        match cmp_mag(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(other.negative, sub_mag(&other.magnitude, &self.magnitude)),
+            _ => BigInt::new(self.negative, sub_mag(&self.magnitude, &other.magnitude)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> Result<BigInt, String> {
+        let bits = self.bits() + other.bits();
+        if bits > MAX_BITS {
+            return Err("integer too large".to_string());
+        }
+        limits::check((bits / 8) as usize, 0)?;
+        Ok(BigInt::new(self.negative != other.negative, mul_mag(&self.magnitude, &other.magnitude)?))
+    }
+
+    /// Division rounding towards zero, with a remainder that has the sign of
+    /// `self`, like `/` and `%` on Rust integers. `None` if `other` is zero.
+    pub fn divrem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = divrem_mag(&self.magnitude, &other.magnitude);
+        Some((BigInt::new(self.negative != other.negative, q), BigInt::new(self.negative, r)))
+    }
+
+    /// Division rounding towards negative infinity. `None` if `other` is zero.
+    pub fn div_floor(&self, other: &BigInt) -> Option<BigInt> {
+        let (q, r) = self.divrem(other)?;
+        if !r.is_zero() && r.negative != other.negative {
+            Some(q.sub(&BigInt::from_i64(1)))
+        } else {
+            Some(q)
+        }
+    }
+
+    // The lowest `len` digits of the two's complement representation.
+    fn twos_complement(&self, len: usize) -> Vec<u32> {
+        let mut digits = self.magnitude.clone();
+        digits.resize(len, 0);
+        if self.negative {
+            for d in digits.iter_mut() {
+                *d = !*d;
+            }
+            let mut carry = 1u64;
+            for d in digits.iter_mut() {
+                let sum = *d as u64 + carry;
+                *d = sum as u32;
+                carry = sum >> 32;
+            }
+        }
+        digits
+    }
+
+    /// Applies a bitwise operation as if both numbers were in two's complement
+    /// with infinitely many sign bits, like Python's ints.
+    pub fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let len = self.magnitude.len().max(other.magnitude.len()) + 1;
+        let a = self.twos_complement(len);
+        let b = other.twos_complement(len);
+        let digits = a.iter().zip(b.iter()).map(|(&x, &y)| op(x, y)).collect::<Vec<_>>();
+
+        if digits[len - 1] >> 31 == 1 {
+            let positive = BigInt::new(false, digits.iter().map(|d| !d).collect());
+            positive.add(&BigInt::from_i64(1)).neg()
+        } else {
+            BigInt::new(false, digits)
+        }
+    }
+
+    pub fn shl(&self, bits: u64) -> Result<BigInt, String> {
+        self.mul(&BigInt::from_i64(2).pow(bits)?)
+    }
+
+    /// Shifts right, rounding towards negative infinity like `>>` on i64.
+    pub fn shr(&self, bits: u64) -> BigInt {
+        if bits >= self.bits() {
+            return BigInt::from_i64(if self.negative { -1 } else { 0 });
+        }
+        self.div_floor(&BigInt::from_i64(2).pow(bits).unwrap()).unwrap()
+    }
+
+    pub fn pow(&self, mut exp: u64) -> Result<BigInt, String> {
+        if self.bits().saturating_sub(1).saturating_mul(exp) > MAX_BITS {
+            return Err("integer too large".to_string());
+        }
+        limits::check((self.bits().saturating_sub(1) * exp / 8) as usize, 0)?;
+
+        let mut result = BigInt::from_i64(1);
+        let mut base = self.clone();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// 10 to the power of `exp`.
+    pub fn pow10(exp: u32) -> BigInt {
+        BigInt::from_i64(10).pow(exp as u64).unwrap()
+    }
+
+    /// The magnitude in base 2, 8, 10 or 16, without a sign.
+    pub fn to_str_radix(&self, radix: u32, uppercase: bool) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+
+        let mut digits = Vec::new();
+        let mut mag = self.magnitude.clone();
+        while !mag.is_empty() {
+            let (q, r) = div_small(&mag, radix);
+            let c = ::std::char::from_digit(r, radix).unwrap();
+            digits.push(if uppercase { c.to_ascii_uppercase() } else { c });
+            mag = q;
+        }
+        digits.iter().rev().collect()
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_mag(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}{}", if self.negative { "-" } else { "" }, self.to_str_radix(10, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        BigInt::parse(s).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!(a.add(&b).to_string(), "-864197532086419753208641975320");
+        assert_eq!(a.sub(&b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(a.mul(&b).unwrap().to_string(), "-121932631137021795226185032733622923332237463801111263526900");
+
+        let (q, r) = b.divrem(&a).unwrap();
+        assert_eq!((q.to_string(), r.to_string()), ("-8".to_string(), "-9000000000900000000090".to_string()));
+        assert!(a.divrem(&BigInt::from_i64(0)).is_none());
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(big("-9223372036854775808").to_i64(), Some(i64::MIN));
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(BigInt::from_i64(-255).to_str_radix(16, false), "ff");
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(BigInt::from_i64(2).pow(100).unwrap().to_string(), "1267650600228229401496703205376");
+        assert!(BigInt::from_i64(2).pow(1 << 40).is_err());
+        assert!(BigInt::parse("12a").is_none());
+        assert_eq!(BigInt::parse_radix("-ff", 16), Some(BigInt::from_i64(-255)));
+    }
+
+    #[test]
+    fn bits() {
+        let a = big("-12345678901234567890123");
+        let b = big("98765432109876543210");
+        assert_eq!(a.bitwise(&b, |x, y| x & y).to_string(), "1729664867975313952");
+        assert_eq!(a.bitwise(&b, |x, y| x | y).to_string(), "-12248643133992666660865");
+        assert_eq!(a.bitwise(&b, |x, y| x ^ y).to_string(), "-12250372798860641974817");
+        assert_eq!(a.shr(10).to_string(), "-12056327051986882706");
+        assert_eq!(b.shl(70).unwrap().to_string(), "116601641565454603531475013448009556951040");
+        assert_eq!(big("-7").div_floor(&big("2")), Some(big("-4")));
+    }
+}
@@ -0,0 +1,227 @@
+use super::*;
+
+use std::cmp::Ordering;
+
+// Arrays are values, so none of these modify their argument. Functions that
+// change an array return the new array instead, e.g. `a = push(a, 4);`
+
+pub fn push(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("push", &args, 2, usize::MAX)?;
+    let mut iter = args.into_iter();
+    let mut vec = expect_array("push", iter.next().unwrap())?;
+    Rc::make_mut(&mut vec).extend(iter);
+    Ok(changed_array(vec))
+}
+
+pub fn pop(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("pop", &args, 1, 1)?;
+    let mut vec = expect_array("pop", args.into_iter().next().unwrap())?;
+    if Rc::make_mut(&mut vec).pop().is_none() {
+        return Err("pop: array is empty".to_string());
+    }
+    Ok(changed_array(vec))
+}
+
+/// The element `pop` removes, so `let x = last(a); a = pop(a);` takes it off the end.
+pub fn last(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("last", &args, 1, 1)?;
+    let vec = expect_array("last", args.into_iter().next().unwrap())?;
+    match vec.last() {
+        Some(value) => Ok(value.clone()),
+        None => Err("last: array is empty".to_string()),
+    }
+}
+
+pub fn insert(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("insert", &args, 3, 3)?;
+    let index = expect_index("insert", &args[1])?;
+    let mut iter = args.into_iter();
+    let mut vec = expect_array("insert", iter.next().unwrap())?;
+    let value = iter.nth(1).unwrap();
+
+    if index > vec.len() {
+        return Err(format!("insert: attempted to insert at index {} of array with length of {}", index, vec.len()));
+    }
+    Rc::make_mut(&mut vec).insert(index, value);
+    Ok(changed_array(vec))
+}
+
+pub fn remove(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("remove", &args, 2, 2)?;
+    let index = expect_index("remove", &args[1])?;
+    let mut vec = expect_array("remove", args.into_iter().next().unwrap())?;
+
+    if index >= vec.len() {
+        return Err(format!("remove: attempted to remove index {} of array with length of {}", index, vec.len()));
+    }
+    Rc::make_mut(&mut vec).remove(index);
+    Ok(changed_array(vec))
+}
+
+pub fn concat(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("concat", &args, 1, usize::MAX)?;
+    let mut result = Vec::new();
+    for arg in args {
+        result.extend(expect_array("concat", arg)?.iter().cloned());
+    }
+    Ok(Value::array(result))
+}
+
+pub fn slice(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("slice", &args, 2, 3)?;
+    let start = expect_index("slice", &args[1])?;
+    let end = match args.get(2) {
+        Some(v) => Some(expect_index("slice", v)?),
+        None => None,
+    };
+    let vec = expect_array("slice", args.into_iter().next().unwrap())?;
+    let end = end.unwrap_or(vec.len());
+
+    if start > end || end > vec.len() {
+        return Err(format!("slice: invalid range {}..{} of array with length of {}", start, end, vec.len()));
+    }
+    Ok(Value::array(vec[start..end].to_vec()))
+}
+
+pub fn reverse(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("reverse", &args, 1, 1)?;
+    let mut vec = expect_array("reverse", args.into_iter().next().unwrap())?;
+    Rc::make_mut(&mut vec).reverse();
+    Ok(changed_array(vec))
+}
+
+pub fn contains(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("contains", &args, 2, 2)?;
+    let mut iter = args.into_iter();
+    let vec = expect_array("contains", iter.next().unwrap())?;
+    let needle = iter.next().unwrap();
+    Ok(Value::Boolean(vec.iter().any(|v| values_equal(v, &needle))))
+}
+
+pub fn index_of(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("index_of", &args, 2, 2)?;
+    let mut iter = args.into_iter();
+    let vec = expect_array("index_of", iter.next().unwrap())?;
+    let needle = iter.next().unwrap();
+    match vec.iter().position(|v| values_equal(v, &needle)) {
+        Some(i) => Ok(Value::Int(i as i64)),
+        None => Ok(Value::Int(-1)),
+    }
+}
+
+pub fn sort(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("sort", &args, 1, 2)?;
+    let mut iter = args.into_iter();
+    let vec = expect_array("sort", iter.next().unwrap())?;
+
+    let sorted = match iter.next() {
+        Some(comparator) => {
+            merge_sort(Rc::unwrap_or_clone(vec), &mut |a, b| {
+                let result = ctx.call("sort", 1, &comparator, vec![a.clone(), b.clone()])?;
+                match result.as_float() {
+                    Some(n) => Ok(n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                    None => Err(format!("sort: comparator must return num, found {}", result.get_type())),
+                }
+            })?
+        },
+        None => merge_sort(Rc::unwrap_or_clone(vec), &mut |a, b| Ok(compare_values(a, b)))?,
+    };
+
+    Ok(Value::array(sorted))
+}
+
+// A stable sort that tolerates comparators which fail or don't define a
+// consistent order, neither of which `slice::sort_by` allows.
+fn merge_sort<F>(mut vec: Vec<Value>, cmp: &mut F) -> Result<Vec<Value>, String>
+    where F: FnMut(&Value, &Value) -> Result<Ordering, String>
+{
+    if vec.len() <= 1 {
+        return Ok(vec);
+    }
+
+    let right = vec.split_off(vec.len() / 2);
+    let left = merge_sort(vec, cmp)?;
+    let right = merge_sort(right, cmp)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    loop {
+        let take_left = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => cmp(l, r)? != Ordering::Greater,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+
+        if take_left {
+            merged.push(left.next().unwrap());
+        } else {
+            merged.push(right.next().unwrap());
+        }
+    }
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use env::Env;
+
+    fn call(f: Builtin, args: Vec<Value>) -> Result<Value, String> {
+        f(&mut Context::new(&mut |_, _| unreachable!(), &[], &Env::default()), args)
+    }
+
+    fn nums(ns: &[f64]) -> Value {
+        Value::array(ns.iter().map(|n| Value::Num(*n)).collect())
+    }
+
+    #[test]
+    fn sort_mixed_types() {
+        let unsorted = Value::array(vec![
+            Value::string("b".to_string()),
+            nums(&[1.0, 2.0]),
+            Value::Num(3.0),
+            nums(&[1.0]),
+            Value::Boolean(true),
+            Value::Num(-1.0),
+        ]);
+        let sorted = Value::array(vec![
+            Value::Boolean(true),
+            Value::Num(-1.0),
+            Value::Num(3.0),
+            Value::string("b".to_string()),
+            nums(&[1.0]),
+            nums(&[1.0, 2.0]),
+        ]);
+        assert_eq!(call(sort, vec![unsorted]), Ok(sorted));
+    }
+
+    #[test]
+    fn sort_with_comparator() {
+        let mut descending = |_: Value, args: Vec<Value>| {
+            match (&args[0], &args[1]) {
+                (&Value::Num(a), &Value::Num(b)) => Ok(Value::Num(b - a)),
+                _ => unreachable!(),
+            }
+        };
+        let vars = Env::default();
+        let mut ctx = Context::new(&mut descending, &[], &vars);
+        let result = sort(&mut ctx, vec![nums(&[2.0, 3.0, 1.0]), Value::Void]);
+        assert_eq!(result, Ok(nums(&[3.0, 2.0, 1.0])));
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(call(insert, vec![nums(&[1.0]), Value::Num(2.0), Value::Num(0.0)]).is_err());
+        assert!(call(remove, vec![nums(&[1.0]), Value::Num(1.0)]).is_err());
+        assert!(call(slice, vec![nums(&[1.0]), Value::Num(1.0), Value::Num(0.0)]).is_err());
+        assert!(call(pop, vec![nums(&[])]).is_err());
+        assert!(call(last, vec![nums(&[])]).is_err());
+        assert_eq!(call(last, vec![nums(&[1.0, 2.0])]), Ok(Value::Num(2.0)));
+        assert!(call(remove, vec![nums(&[1.0]), Value::Num(0.5)]).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+
+// Like arrays, dicts are values: `insert` and `remove` return a new dict.
+
+type Dict = BTreeMap<String, Value>;
+
+fn dict_and_key(name: &str, args: Vec<Value>) -> Result<(Dict, String, Option<Value>), String> {
+    let key = expect_string(name, &args[1])?.to_string();
+    let mut iter = args.into_iter();
+    let dict = expect_dict(name, iter.next().unwrap())?;
+    Ok((dict, key, iter.nth(1)))
+}
+
+/// The keys, in sorted order.
+pub fn keys(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("keys", &args, 1, 1)?;
+    let dict = expect_dict("keys", args.into_iter().next().unwrap())?;
+    Ok(Value::array(dict.into_keys().map(Value::string).collect()))
+}
+
+/// The values, in the order of their keys.
+pub fn values(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("values", &args, 1, 1)?;
+    let dict = expect_dict("values", args.into_iter().next().unwrap())?;
+    Ok(Value::array(dict.into_values().collect()))
+}
+
+pub fn insert(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("insert", &args, 3, 3)?;
+    let (mut dict, key, value) = dict_and_key("insert", args)?;
+    dict.insert(key, value.unwrap());
+    Ok(Value::dict(dict))
+}
+
+pub fn remove(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("remove", &args, 2, 2)?;
+    let (mut dict, key, _) = dict_and_key("remove", args)?;
+    if dict.remove(&key).is_none() {
+        return Err(format!("remove: key not found in dict: {}", key));
+    }
+    Ok(Value::dict(dict))
+}
+
+pub fn contains(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("contains", &args, 2, 2)?;
+    let (dict, key, _) = dict_and_key("contains", args)?;
+    Ok(Value::Boolean(dict.contains_key(&key)))
+}
@@ -0,0 +1,352 @@
+use super::*;
+
+use std::cmp;
+use std::iter;
+
+use bigint::BigInt;
+use decimal::Decimal;
+
+// Format strings look like Rust's: `{}` takes the next argument, `{1}` a
+// positional one and `{name}` a variable. After a colon comes an optional
+// spec, `[[fill]align][+][#][0][width][.precision][type]`, where align is one
+// of `<`, `^`, `>` and type is one of `x`, `X`, `o`, `b`, `e`, `E`.
+// `{{` and `}}` produce literal braces.
+//
+// Like Rust's, every argument has to be used, and widths and precisions can't
+// be more than `MAX_WIDTH`.
+
+/// The largest width or precision a placeholder can have.
+const MAX_WIDTH: usize = 65535;
+
+#[derive(Debug, Default, PartialEq)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    plus: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+fn parse_number(chars: &[char], pos: &mut usize, spec: &str) -> Result<Option<usize>, String> {
+    let start = *pos;
+    while *pos < chars.len() && chars[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+
+    if *pos == start {
+        return Ok(None);
+    }
+    match chars[start..*pos].iter().collect::<String>().parse() {
+        Ok(n) if n <= MAX_WIDTH => Ok(Some(n)),
+        _ => Err(format!("width or precision in {{:{}}} is more than {}", spec, MAX_WIDTH)),
+    }
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let chars = spec.chars().collect::<Vec<_>>();
+    let is_align = |c: char| c == '<' || c == '^' || c == '>';
+    let mut result = Spec::default();
+    let mut pos = 0;
+
+    if chars.len() >= 2 && is_align(chars[1]) {
+        result.fill = Some(chars[0]);
+        result.align = Some(chars[1]);
+        pos = 2;
+    } else if !chars.is_empty() && is_align(chars[0]) {
+        result.align = Some(chars[0]);
+        pos = 1;
+    }
+
+    if pos < chars.len() && chars[pos] == '+' {
+        result.plus = true;
+        pos += 1;
+    }
+    if pos < chars.len() && chars[pos] == '#' {
+        result.alternate = true;
+        pos += 1;
+    }
+    if pos < chars.len() && chars[pos] == '0' {
+        result.zero = true;
+        pos += 1;
+    }
+
+    result.width = parse_number(&chars, &mut pos, spec)?;
+
+    if pos < chars.len() && chars[pos] == '.' {
+        pos += 1;
+        result.precision = parse_number(&chars, &mut pos, spec)?;
+        if result.precision.is_none() {
+            return Err(format!("missing precision after '.' in {{:{}}}", spec));
+        }
+    }
+
+    match &chars[pos..] {
+        &[] => {},
+        &[c] if "xXobeE".contains(c) => result.kind = Some(c),
+        rest => return Err(format!("unknown format type '{}' in {{:{}}}", rest.iter().collect::<String>(), spec)),
+    }
+
+    Ok(result)
+}
+
+// Formats the magnitude of a number, without its sign.
+fn format_magnitude(n: f64, spec: &Spec) -> Result<String, String> {
+    let n = n.abs();
+    match spec.kind {
+        None => match spec.precision {
+            Some(p) => Ok(format!("{:.*}", p, n)),
+            None => Ok(n.to_string()),
+        },
+        Some('e') | Some('E') => {
+            let s = match spec.precision {
+                Some(p) => format!("{:.*e}", p, n),
+                None => format!("{:e}", n),
+            };
+            Ok(if spec.kind == Some('E') { s.to_uppercase() } else { s })
+        },
+        Some(kind) => {
+            if n.fract() != 0.0 || n > 2f64.powi(53) {
+                return Err(format!("cannot format {} as {}, it is not an integer", n, match kind {
+                    'b' => "binary",
+                    'o' => "octal",
+                    _ => "hex",
+                }));
+            }
+            Ok(format_radix(n as u64, kind, spec.alternate))
+        },
+    }
+}
+
+// Ints are formatted exactly, except where the spec calls for a fraction or an exponent.
+fn format_int_magnitude(i: i64, spec: &Spec) -> Result<String, String> {
+    match (spec.kind, spec.precision) {
+        (None, None) => Ok(i.unsigned_abs().to_string()),
+        (Some(kind), _) if "xXob".contains(kind) => Ok(format_radix(i.unsigned_abs(), kind, spec.alternate)),
+        _ => format_magnitude(i as f64, spec),
+    }
+}
+
+fn format_bigint_magnitude(b: &BigInt, spec: &Spec) -> Result<String, String> {
+    match (spec.kind, spec.precision) {
+        (None, None) => Ok(b.to_str_radix(10, false)),
+        (Some(kind), _) if "xXob".contains(kind) => {
+            let (radix, prefix) = match kind {
+                'x' | 'X' => (16, "0x"),
+                'o' => (8, "0o"),
+                _ => (2, "0b"),
+            };
+            let digits = b.to_str_radix(radix, kind == 'X');
+            Ok(if spec.alternate { format!("{}{}", prefix, digits) } else { digits })
+        },
+        _ => format_magnitude(b.to_f64(), spec),
+    }
+}
+
+// A precision rounds decimals exactly, rather than going through a num.
+fn format_decimal_magnitude(d: &Decimal, spec: &Spec) -> Result<String, String> {
+    let rounded = match (spec.kind, spec.precision) {
+        (None, None) => d.clone(),
+        (None, Some(p)) => d.round(p as u32)?,
+        _ => return format_magnitude(d.to_f64(), spec),
+    };
+    Ok(rounded.to_string().trim_start_matches('-').to_string())
+}
+
+fn format_radix(n: u64, kind: char, alternate: bool) -> String {
+    let (digits, prefix) = match kind {
+        'x' => (format!("{:x}", n), "0x"),
+        'X' => (format!("{:X}", n), "0x"),
+        'o' => (format!("{:o}", n), "0o"),
+        _ => (format!("{:b}", n), "0b"),
+    };
+    if alternate { format!("{}{}", prefix, digits) } else { digits }
+}
+
+fn pad(s: String, spec: &Spec, default_align: char) -> String {
+    let width = spec.width.unwrap_or(0);
+    let len = s.chars().count();
+    if len >= width {
+        return s;
+    }
+
+    let fill = spec.fill.unwrap_or(' ');
+    let padding = width - len;
+    let (left, right) = match spec.align.unwrap_or(default_align) {
+        '<' => (0, padding),
+        '^' => (padding / 2, padding - padding / 2),
+        _ => (padding, 0),
+    };
+
+    let mut result = iter::repeat_n(fill, left).collect::<String>();
+    result.push_str(&s);
+    result.extend(iter::repeat_n(fill, right));
+    result
+}
+
+fn format_value(value: &Value, spec: &Spec) -> Result<String, String> {
+    match *value {
+        Value::Num(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) => {
+            let (magnitude, negative) = match *value {
+                Value::Int(i) => (format_int_magnitude(i, spec)?, i < 0),
+                Value::BigInt(ref b) => (format_bigint_magnitude(b, spec)?, b.is_negative()),
+                Value::Decimal(ref d) => (format_decimal_magnitude(d, spec)?, d.is_negative()),
+                Value::Num(n) => (format_magnitude(n, spec)?, n.is_sign_negative() && n != 0.0 && !n.is_nan()),
+                _ => unreachable!(),
+            };
+            let sign = if negative {
+                "-"
+            } else if spec.plus {
+                "+"
+            } else {
+                ""
+            };
+
+            // Zero padding goes between the sign (and prefix) and the digits
+            if spec.zero && spec.align.is_none() {
+                let width = spec.width.unwrap_or(0);
+                let prefix_len = if spec.alternate && spec.kind.map_or(false, |k| "xXob".contains(k)) { 2 } else { 0 };
+                let (prefix, digits) = magnitude.split_at(prefix_len);
+                let zeros = width.saturating_sub(sign.len() + magnitude.chars().count());
+                let padded = "0".repeat(zeros);
+                return Ok(format!("{}{}{}{}", sign, prefix, padded, digits));
+            }
+
+            Ok(pad(format!("{}{}", sign, magnitude), spec, '>'))
+        },
+        _ => {
+            if let Some(kind) = spec.kind {
+                return Err(format!("cannot format {} with type '{}'", value.get_type(), kind));
+            }
+
+            let s = value.to_string();
+            let s = match spec.precision {
+                Some(p) => s.chars().take(p).collect(),
+                None => s,
+            };
+            Ok(pad(s, spec, '<'))
+        },
+    }
+}
+
+/// Fills in the placeholders of `fmt`. `lookup` resolves `{name}` placeholders.
+pub fn format_string(fmt: &str, args: &[Value], lookup: &dyn Fn(&str) -> Option<Value>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = fmt.chars().peekable();
+    let mut next_arg = 0;
+    let mut used = vec![false; args.len()];
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            },
+            '}' => return Err("unmatched '}' in format string".to_string()),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err("unmatched '{' in format string".to_string()),
+                    }
+                }
+
+                let (name, spec) = match placeholder.find(':') {
+                    Some(i) => (&placeholder[..i], &placeholder[i + 1..]),
+                    None => (&placeholder[..], ""),
+                };
+                let spec = parse_spec(spec)?;
+
+                let index = if name.is_empty() {
+                    next_arg += 1;
+                    Some(next_arg - 1)
+                } else {
+                    name.parse::<usize>().ok()
+                };
+
+                let named;
+                let value = if let Some(i) = index {
+                    let arg = args.get(i).ok_or(format!("missing argument {} for format string", i))?;
+                    used[i] = true;
+                    arg
+                } else {
+                    named = lookup(name).ok_or(format!("Undefined variable: {}", name))?;
+                    &named
+                };
+
+                // A width, or a precision for a number, makes the text at least that long
+                let precision = if number::is_number(value) { spec.precision.unwrap_or(0) } else { 0 };
+                let length = cmp::max(spec.width.unwrap_or(0), precision);
+                limits::check(length, length)?;
+                result.push_str(&format_value(value, &spec)?);
+            },
+            _ => result.push(c),
+        }
+    }
+
+    match used.iter().position(|&u| !u) {
+        Some(i) => Err(format!("argument {} is never used by the format string", i)),
+        None => Ok(result),
+    }
+}
+
+pub fn format(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("format", &args, 1, usize::MAX)?;
+    let fmt = expect_string("format", &args[0])?;
+    let lookup = |name: &str| ctx.vars.lookup(name);
+    format_string(fmt, &args[1..], &lookup)
+        .map(Value::string)
+        .map_err(|e| format!("format: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(f: &str, args: &[Value]) -> Result<String, String> {
+        format_string(f, args, &|name| if name == "x" { Some(Value::Num(1.5)) } else { None })
+    }
+
+    #[test]
+    fn precision_width_and_alignment() {
+        assert_eq!(fmt("{:>8.2}|{:<5}|{:^7}", &[Value::Num(0.1 + 0.2), Value::string("ab".to_string()), Value::Boolean(true)]),
+                   Ok("    0.30|ab   | true  ".to_string()));
+        assert_eq!(fmt("{:*^9.1}", &[Value::Num(-2.25)]), Ok("**-2.2***".to_string()));
+        assert_eq!(fmt("{:05.1}|{:+}", &[Value::Num(-2.0), Value::Num(3.0)]), Ok("-02.0|+3".to_string()));
+    }
+
+    #[test]
+    fn positional_and_named() {
+        assert_eq!(fmt("{1} {0} {} {x:.2}", &[Value::Num(1.0), Value::Num(2.0)]), Ok("2 1 1 1.50".to_string()));
+        assert!(fmt("{y}", &[]).is_err());
+        assert!(fmt("{} {}", &[Value::Num(1.0)]).is_err());
+        assert_eq!(fmt("{}", &[Value::Num(1.0), Value::Num(2.0)]), Err("argument 1 is never used by the format string".to_string()));
+        assert_eq!(fmt("{1}", &[Value::Num(1.0), Value::Num(2.0)]), Err("argument 0 is never used by the format string".to_string()));
+    }
+
+    #[test]
+    fn number_modes() {
+        assert_eq!(fmt("{:x} {:#X} {:#010b} {:o}", &[Value::Num(255.0), Value::Num(255.0), Value::Num(5.0), Value::Num(8.0)]),
+                   Ok("ff 0xFF 0b00000101 10".to_string()));
+        assert_eq!(fmt("{:e} {:.2E}", &[Value::Num(1500.0), Value::Num(0.000123)]), Ok("1.5e3 1.23E-4".to_string()));
+        assert!(fmt("{:x}", &[Value::Num(1.5)]).is_err());
+        assert!(fmt("{:x}", &[Value::string("a".to_string())]).is_err());
+        assert_eq!(fmt("{:99999999999}", &[Value::Num(1.0)]), Err("width or precision in {:99999999999} is more than 65535".to_string()));
+        assert!(fmt("{:.65536}", &[Value::Num(1.0)]).is_err());
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(fmt("{{}} {}", &[Value::Num(1.0)]), Ok("{} 1".to_string()));
+        assert!(fmt("{", &[]).is_err());
+        assert!(fmt("}", &[]).is_err());
+    }
+}
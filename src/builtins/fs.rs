@@ -0,0 +1,130 @@
+use super::*;
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+// Relative paths are resolved against the current directory, as usual. Errors
+// always name the path, since the OS message alone ("No such file or
+// directory") doesn't say which file was meant.
+
+fn path_arg(name: &str, args: &[Value], i: usize) -> Result<String, String> {
+    expect_string(name, &args[i]).map(|s| s.to_string())
+}
+
+fn io_error(name: &str, path: &str, e: ::std::io::Error) -> String {
+    format!("{}: {}: {}", name, path, e)
+}
+
+pub fn read_file(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("read_file", &args, 1, 1)?;
+    let path = path_arg("read_file", &args, 0)?;
+    // A file too big for the memory limit isn't read at all
+    let size = fs::metadata(&path).map_err(|e| io_error("read_file", &path, e))?.len();
+    limits::check(size as usize, 0)?;
+    fs::read_to_string(&path)
+        .map(Value::string)
+        .map_err(|e| io_error("read_file", &path, e))
+}
+
+pub fn write_file(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("write_file", &args, 2, 2)?;
+    let path = path_arg("write_file", &args, 0)?;
+    let contents = expect_string("write_file", &args[1])?;
+    fs::write(&path, contents)
+        .map(|_| Value::Void)
+        .map_err(|e| io_error("write_file", &path, e))
+}
+
+/// Creates the file if it doesn't exist.
+pub fn append_file(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("append_file", &args, 2, 2)?;
+    let path = path_arg("append_file", &args, 0)?;
+    let contents = expect_string("append_file", &args[1])?;
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map(|_| Value::Void)
+        .map_err(|e| io_error("append_file", &path, e))
+}
+
+pub fn exists(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("exists", &args, 1, 1)?;
+    let path = path_arg("exists", &args, 0)?;
+    Ok(Value::Boolean(fs::metadata(&path).is_ok()))
+}
+
+pub fn is_dir(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("is_dir", &args, 1, 1)?;
+    let path = path_arg("is_dir", &args, 0)?;
+    Ok(Value::Boolean(fs::metadata(&path).map(|m| m.is_dir()).unwrap_or(false)))
+}
+
+/// Returns the names of the entries in a directory, sorted.
+pub fn list_dir(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("list_dir", &args, 1, 1)?;
+    let path = path_arg("list_dir", &args, 0)?;
+    let entries = fs::read_dir(&path).map_err(|e| io_error("list_dir", &path, e))?;
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| io_error("list_dir", &path, e))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    Ok(Value::array(names.into_iter().map(Value::string).collect()))
+}
+
+/// Creates a directory along with any missing parents.
+pub fn mkdir(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("mkdir", &args, 1, 1)?;
+    let path = path_arg("mkdir", &args, 0)?;
+    fs::create_dir_all(&path)
+        .map(|_| Value::Void)
+        .map_err(|e| io_error("mkdir", &path, e))
+}
+
+/// Removes a file, or a directory and everything in it.
+pub fn remove_file(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("remove_file", &args, 1, 1)?;
+    let path = path_arg("remove_file", &args, 0)?;
+    let metadata = fs::symlink_metadata(&path).map_err(|e| io_error("remove_file", &path, e))?;
+
+    let result = if metadata.is_dir() {
+        fs::remove_dir_all(&path)
+    } else {
+        fs::remove_file(&path)
+    };
+    result.map(|_| Value::Void).map_err(|e| io_error("remove_file", &path, e))
+}
+
+pub fn rename(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("rename", &args, 2, 2)?;
+    let from = path_arg("rename", &args, 0)?;
+    let to = path_arg("rename", &args, 1)?;
+    fs::rename(&from, &to)
+        .map(|_| Value::Void)
+        .map_err(|e| format!("rename: {} to {}: {}", from, to, e))
+}
+
+/// Returns `[size, is_dir, modified]`, with the modification time in seconds since the Unix epoch.
+pub fn stat(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("stat", &args, 1, 1)?;
+    let path = path_arg("stat", &args, 0)?;
+    let metadata = fs::metadata(&path).map_err(|e| io_error("stat", &path, e))?;
+
+    let modified = metadata.modified()
+        .ok()
+        .and_then(|t| t.duration_since(::std::time::UNIX_EPOCH).ok())
+        .map(|d| Value::Num(d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9))
+        .unwrap_or(Value::Void);
+
+    Ok(Value::array(vec![
+        Value::Int(metadata.len() as i64),
+        Value::Boolean(metadata.is_dir()),
+        modified,
+    ]))
+}
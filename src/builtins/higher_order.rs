@@ -0,0 +1,206 @@
+use super::*;
+
+// Splits `(array, callback)` arguments, which most of these functions take.
+fn array_and_func(name: &str, args: Vec<Value>) -> Result<(Vec<Value>, Value), String> {
+    check_arg_count(name, &args, 2, 2)?;
+    let mut iter = args.into_iter();
+    let vec = expect_array(name, iter.next().unwrap())?;
+    Ok((Rc::unwrap_or_clone(vec), iter.next().unwrap()))
+}
+
+fn expect_predicate_result(name: &str, value: Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(b) => Ok(b),
+        _ => Err(format!("{}: callback must return bool, found {}", name, value.get_type())),
+    }
+}
+
+pub fn map(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("map", args)?;
+    let mut result = Vec::with_capacity(vec.len());
+    for item in vec {
+        result.push(ctx.call("map", 1, &func, vec![item])?);
+    }
+    Ok(Value::array(result))
+}
+
+pub fn filter(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("filter", args)?;
+    let mut result = Vec::new();
+    for item in vec {
+        let keep = ctx.call("filter", 1, &func, vec![item.clone()])?;
+        if expect_predicate_result("filter", keep)? {
+            result.push(item);
+        }
+    }
+    Ok(Value::array(result))
+}
+
+/// `reduce(array, f)` starts from the first element, `reduce(array, f, initial)` from `initial`.
+pub fn reduce(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("reduce", &args, 2, 3)?;
+    let mut args = args.into_iter();
+    let mut iter = Rc::unwrap_or_clone(expect_array("reduce", args.next().unwrap())?).into_iter();
+    let func = args.next().unwrap();
+
+    let mut acc = match args.next() {
+        Some(initial) => initial,
+        None => match iter.next() {
+            Some(first) => first,
+            None => return Err("reduce: empty array with no initial value".to_string()),
+        },
+    };
+    for item in iter {
+        acc = ctx.call("reduce", 1, &func, vec![acc, item])?;
+    }
+    Ok(acc)
+}
+
+/// `fold(array, initial, f)`
+pub fn fold(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("fold", &args, 3, 3)?;
+    let mut args = args.into_iter();
+    let vec = Rc::unwrap_or_clone(expect_array("fold", args.next().unwrap())?);
+    let mut acc = args.next().unwrap();
+    let func = args.next().unwrap();
+
+    for item in vec {
+        acc = ctx.call("fold", 2, &func, vec![acc, item])?;
+    }
+    Ok(acc)
+}
+
+pub fn any(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("any", args)?;
+    for item in vec {
+        let result = ctx.call("any", 1, &func, vec![item])?;
+        if expect_predicate_result("any", result)? {
+            return Ok(Value::Boolean(true));
+        }
+    }
+    Ok(Value::Boolean(false))
+}
+
+pub fn all(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("all", args)?;
+    for item in vec {
+        let result = ctx.call("all", 1, &func, vec![item])?;
+        if !expect_predicate_result("all", result)? {
+            return Ok(Value::Boolean(false));
+        }
+    }
+    Ok(Value::Boolean(true))
+}
+
+/// Returns the first matching element, or `void` if there is none.
+pub fn find(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("find", args)?;
+    for item in vec {
+        let result = ctx.call("find", 1, &func, vec![item.clone()])?;
+        if expect_predicate_result("find", result)? {
+            return Ok(item);
+        }
+    }
+    Ok(Value::Void)
+}
+
+/// Stops at the end of the shortest array.
+pub fn zip(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("zip", &args, 1, usize::MAX)?;
+    let arrays = args.into_iter().map(|a| expect_array("zip", a)).collect::<Result<Vec<_>, _>>()?;
+    let len = arrays.iter().map(|a| a.len()).min().unwrap();
+
+    let result = (0..len).map(|i| Value::array(arrays.iter().map(|a| a[i].clone()).collect())).collect();
+    Ok(Value::array(result))
+}
+
+pub fn enumerate(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("enumerate", &args, 1, 1)?;
+    let vec = expect_array("enumerate", args.into_iter().next().unwrap())?;
+    let result = vec.iter()
+        .cloned()
+        .enumerate()
+        .map(|(i, item)| Value::array(vec![Value::Int(i as i64), item]))
+        .collect();
+    Ok(Value::array(result))
+}
+
+pub fn flat_map(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("flat_map", args)?;
+    let mut result = Vec::new();
+    for item in vec {
+        match ctx.call("flat_map", 1, &func, vec![item])? {
+            Value::Array(items) => result.extend(Rc::unwrap_or_clone(items)),
+            other => return Err(format!("flat_map: callback must return array, found {}", other.get_type())),
+        }
+    }
+    Ok(Value::array(result))
+}
+
+/// Returns `[key, items]` pairs, with keys in the order they were first produced.
+pub fn group_by(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let (vec, func) = array_and_func("group_by", args)?;
+    let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+    for item in vec {
+        let key = ctx.call("group_by", 1, &func, vec![item.clone()])?;
+        match groups.iter().position(|g| values_equal(&g.0, &key)) {
+            Some(i) => groups[i].1.push(item),
+            None => groups.push((key, vec![item])),
+        }
+    }
+
+    let result = groups.into_iter()
+        .map(|(key, items)| Value::array(vec![key, Value::array(items)]))
+        .collect();
+    Ok(Value::array(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use env::Env;
+
+    fn nums(ns: &[f64]) -> Value {
+        Value::array(ns.iter().map(|n| Value::Num(*n)).collect())
+    }
+
+    // Stands in for a script function `func(x: num) { return x % 2; }`
+    fn parity(_: Value, args: Vec<Value>) -> Result<Value, String> {
+        match args[0] {
+            Value::Num(n) => Ok(Value::Num(n % 2.0)),
+            _ => Err("invalid operation".to_string()),
+        }
+    }
+
+    #[test]
+    fn zip_stops_at_shortest() {
+        let mut f = parity;
+        let vars = Env::default();
+        let mut ctx = Context::new(&mut f, &[], &vars);
+        let result = zip(&mut ctx, vec![nums(&[1.0, 2.0, 3.0]), nums(&[4.0, 5.0])]);
+        assert_eq!(result, Ok(Value::array(vec![nums(&[1.0, 4.0]), nums(&[2.0, 5.0])])));
+    }
+
+    #[test]
+    fn group_by_keeps_first_seen_order() {
+        let mut f = parity;
+        let vars = Env::default();
+        let mut ctx = Context::new(&mut f, &[], &vars);
+        let result = group_by(&mut ctx, vec![nums(&[2.0, 1.0, 4.0]), Value::Void]);
+        assert_eq!(result, Ok(Value::array(vec![
+            Value::array(vec![Value::Num(0.0), nums(&[2.0, 4.0])]),
+            Value::array(vec![Value::Num(1.0), nums(&[1.0])]),
+        ])));
+    }
+
+    #[test]
+    fn callback_errors_name_the_callback() {
+        let mut f = parity;
+        let vars = Env::default();
+        let names = [None, Some("parity".to_string())];
+        let mut ctx = Context::new(&mut f, &names, &vars);
+        let result = map(&mut ctx, vec![Value::array(vec![Value::Boolean(true)]), Value::Void]);
+        assert_eq!(result, Err("map: error in callback parity: invalid operation".to_string()));
+    }
+}
@@ -0,0 +1,416 @@
+use super::*;
+
+use std::char;
+
+use bigint::BigInt;
+use number;
+use std::iter;
+
+// JSON maps onto values as you'd expect: numbers to num, objects to dict and
+// null to void. Types and functions have no JSON representation.
+
+/// How deeply arrays and objects can be nested in parsed text, so the parser
+/// can't run out of stack.
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'s> {
+    chars: iter::Peekable<::std::str::Chars<'s>>,
+    line: usize,
+    column: usize,
+    depth: usize, // Arrays and objects the parser is inside of
+}
+
+impl<'s> Parser<'s> {
+    fn new(text: &'s str) -> Self {
+        Parser {
+            chars: text.chars().peekable(),
+            line: 1,
+            column: 1,
+            depth: 0,
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("{} at line {}, column {}", message, self.line, self.column))
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        match c {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            },
+            Some(_) => self.column += 1,
+            None => {},
+        }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek() {
+            self.bump();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            },
+            Some(c) => self.error(&format!("expected '{}', found '{}'", expected, c)),
+            None => self.error(&format!("expected '{}', found end of input", expected)),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            if self.peek() != Some(expected) {
+                return self.error(&format!("invalid literal, expected '{}'", word));
+            }
+            self.bump();
+        }
+        Ok(value)
+    }
+
+    fn parse_document(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(value),
+            Some(c) => self.error(&format!("unexpected '{}' after JSON value", c)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('{') | Some('[') => self.parse_nested(),
+            Some('"') => self.parse_string().map(Value::string),
+            Some('t') => self.expect_word("true", Value::Boolean(true)),
+            Some('f') => self.expect_word("false", Value::Boolean(false)),
+            Some('n') => self.expect_word("null", Value::Void),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) => self.error(&format!("unexpected '{}'", c)),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn parse_nested(&mut self) -> Result<Value, String> {
+        if self.depth == MAX_DEPTH {
+            return self.error(&format!("arrays and objects nested more than {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let value = if self.peek() == Some('{') { self.parse_object() } else { self.parse_array() };
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut dict = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(Value::dict(dict));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return self.error("expected string key");
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.parse_value()?;
+            dict.insert(key, value);
+            self.skip_whitespace();
+
+            match self.bump() {
+                Some(',') => {},
+                Some('}') => return Ok(Value::dict(dict)),
+                _ => return self.error("expected ',' or '}' in object"),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut vec = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.bump();
+            return Ok(Value::array(vec));
+        }
+
+        loop {
+            self.skip_whitespace();
+            vec.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.bump() {
+                Some(',') => {},
+                Some(']') => return Ok(Value::array(vec)),
+                _ => return self.error("expected ',' or ']' in array"),
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let mut n = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => {
+                    self.bump();
+                    n = n * 16 + d;
+                },
+                None => return self.error("invalid \\u escape"),
+            }
+        }
+        Ok(n)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return self.error("unterminated string"),
+                Some('"') => {
+                    self.bump();
+                    return Ok(s);
+                },
+                Some('\\') => {
+                    self.bump();
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let high = self.parse_hex4()?;
+                            let code = if (0xD800..0xDC00).contains(&high) {
+                                // A surrogate pair encodes a character outside the BMP
+                                if self.bump() != Some('\\') || self.bump() != Some('u') {
+                                    return self.error("unpaired surrogate in \\u escape");
+                                }
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return self.error("unpaired surrogate in \\u escape");
+                                }
+                                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                            } else {
+                                high
+                            };
+
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => return self.error("unpaired surrogate in \\u escape"),
+                            }
+                        },
+                        _ => return self.error("invalid escape in string"),
+                    };
+                    s.push(c);
+                },
+                Some(c) if (c as u32) < 0x20 => return self.error("control character in string"),
+                Some(c) => {
+                    self.bump();
+                    s.push(c);
+                },
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, String> {
+        let (line, column) = (self.line, self.column);
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) {
+                text.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        // Rust accepts a few forms that JSON doesn't, like "1." and "01"
+        let digits = text.trim_start_matches('-');
+        let leading_zero = digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit());
+        let valid = !(digits.starts_with('.') || leading_zero || text.contains(".e") || text.contains(".E") || text.ends_with('.'));
+
+        // Whole numbers become ints, so that ids and counts stay exact
+        let whole = !text.contains(|c| ".eE".contains(c));
+        if let (true, true, Some(i)) = (valid, whole, BigInt::parse(&text)) {
+            return Ok(number::normalize(i));
+        }
+
+        match text.parse::<f64>() {
+            Ok(n) if valid => Ok(Value::Num(n)),
+            _ => Err(format!("invalid number '{}' at line {}, column {}", text, line, column)),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    Parser::new(text).parse_document()
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_value(out: &mut String, value: &Value, indent: &str, depth: usize) -> Result<(), String> {
+    let newline = |out: &mut String, depth: usize| {
+        if !indent.is_empty() {
+            out.push('\n');
+            for _ in 0..depth {
+                out.push_str(indent);
+            }
+        }
+    };
+
+    match *value {
+        Value::Num(n) => {
+            if !n.is_finite() {
+                return Err(format!("{} cannot be represented in JSON", n));
+            }
+            out.push_str(&n.to_string());
+            // Keeps whole nums from coming back as ints
+            if n.fract() == 0.0 {
+                out.push_str(".0");
+            }
+        },
+        Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) => out.push_str(&value.to_string()),
+        Value::String(ref s) => write_string(out, s),
+        Value::Boolean(b) => out.push_str(&b.to_string()),
+        Value::Void => out.push_str("null"),
+        Value::Array(ref vec) => {
+            out.push('[');
+            for (i, item) in vec.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 1);
+                write_value(out, item, indent, depth + 1)?;
+            }
+            if !vec.is_empty() {
+                newline(out, depth);
+            }
+            out.push(']');
+        },
+        Value::Dict(ref dict) => {
+            out.push('{');
+            for (i, (key, item)) in dict.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 1);
+                write_string(out, key);
+                out.push_str(if indent.is_empty() { ":" } else { ": " });
+                write_value(out, item, indent, depth + 1)?;
+            }
+            if !dict.is_empty() {
+                newline(out, depth);
+            }
+            out.push('}');
+        },
+        Value::Type(_) | Value::Func(_, _) => {
+            return Err(format!("{} cannot be represented in JSON", value.get_type()));
+        },
+    }
+
+    Ok(())
+}
+
+/// Without an indent (or with an indent of 0) the output is on a single line.
+pub fn stringify(value: &Value, indent: usize) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, value, &" ".repeat(indent), 0)?;
+    Ok(out)
+}
+
+pub fn json_parse(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("json_parse", &args, 1, 1)?;
+    let text = expect_string("json_parse", &args[0])?;
+    parse(text).map_err(|e| format!("json_parse: {}", e))
+}
+
+/// `json_stringify(value, indent)`, where `indent` is a number of spaces.
+pub fn json_stringify(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("json_stringify", &args, 1, 2)?;
+    let indent = match args.get(1) {
+        Some(v) => expect_index("json_stringify", v)?,
+        None => 0,
+    };
+    stringify(&args[0], indent)
+        .map(Value::string)
+        .map_err(|e| format!("json_stringify: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"a": [1, 2.5, -3e2], "b": {"c": null, "d": true}, "e": "x\"é😀\n"}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(stringify(&value, 0).unwrap(), r#"{"a":[1,2.5,-300.0],"b":{"c":null,"d":true},"e":"x\"é😀\n"}"#);
+        assert_eq!(parse(&stringify(&value, 2).unwrap()), Ok(value));
+    }
+
+    #[test]
+    fn indentation() {
+        let value = parse(r#"{"a": [1, []], "b": {}}"#).unwrap();
+        assert_eq!(stringify(&value, 2).unwrap(), "{\n  \"a\": [\n    1,\n    []\n  ],\n  \"b\": {}\n}");
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(parse("[1,\n  2,\n  x]"), Err("unexpected 'x' at line 3, column 3".to_string()));
+        assert_eq!(parse("{\"a\" 1}"), Err("expected ':', found '1' at line 1, column 6".to_string()));
+        assert_eq!(parse("[1, 2"), Err("expected ',' or ']' in array at line 1, column 6".to_string()));
+
+        let deep = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(parse(&deep).is_ok());
+        let deeper = "[{\"a\": ".repeat(MAX_DEPTH) + &"[]}]".repeat(MAX_DEPTH);
+        assert_eq!(parse(&deeper), Err("arrays and objects nested more than 512 deep at line 1, column 1793".to_string()));
+        assert!(parse("01").is_err());
+        assert!(parse("1.").is_err());
+        assert_eq!(parse("[9007199254740993, 1.0, 1e2]"), Ok(Value::array(vec![
+            Value::Int(9007199254740993),
+            Value::Num(1.0),
+            Value::Num(100.0),
+        ])));
+        assert!(parse("[1] 2").is_err());
+    }
+
+    #[test]
+    fn unrepresentable_values() {
+        assert!(stringify(&Value::Num(f64::NAN), 0).is_err());
+        assert!(stringify(&Value::Type(Type::Num), 0).is_err());
+    }
+}
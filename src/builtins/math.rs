@@ -0,0 +1,248 @@
+use super::*;
+
+use std::cell::Cell;
+use std::f64;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn constants() -> Vec<(&'static str, Value)> {
+    vec![
+        ("PI", Value::Num(f64::consts::PI)),
+        ("E", Value::Num(f64::consts::E)),
+    ]
+}
+
+fn unary(name: &str, args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value, String> {
+    check_arg_count(name, &args, 1, 1)?;
+    Ok(Value::Num(f(expect_num(name, &args[0])?)))
+}
+
+pub fn sqrt(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("sqrt", args, f64::sqrt) }
+pub fn abs(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("abs", args, f64::abs) }
+pub fn floor(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("floor", args, f64::floor) }
+pub fn ceil(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("ceil", args, f64::ceil) }
+pub fn round(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("round", args, f64::round) }
+pub fn trunc(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("trunc", args, f64::trunc) }
+pub fn sin(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("sin", args, f64::sin) }
+pub fn cos(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("cos", args, f64::cos) }
+pub fn tan(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("tan", args, f64::tan) }
+pub fn asin(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("asin", args, f64::asin) }
+pub fn acos(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("acos", args, f64::acos) }
+pub fn atan(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("atan", args, f64::atan) }
+pub fn exp(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("exp", args, f64::exp) }
+pub fn log10(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("log10", args, f64::log10) }
+pub fn log2(_: &mut Context, args: Vec<Value>) -> Result<Value, String> { unary("log2", args, f64::log2) }
+
+/// -1, 0 or 1 (NaN stays NaN)
+pub fn sign(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    unary("sign", args, |n| if n == 0.0 { 0.0 } else { n.signum() })
+}
+
+pub fn atan2(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("atan2", &args, 2, 2)?;
+    let y = expect_num("atan2", &args[0])?;
+    let x = expect_num("atan2", &args[1])?;
+    Ok(Value::Num(y.atan2(x)))
+}
+
+/// `log(x)` is the natural logarithm, `log(x, base)` uses the given base.
+pub fn log(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("log", &args, 1, 2)?;
+    let x = expect_num("log", &args[0])?;
+    match args.get(1) {
+        Some(base) => Ok(Value::Num(x.log(expect_num("log", base)?))),
+        None => Ok(Value::Num(x.ln())),
+    }
+}
+
+// `min` and `max` take either several numbers or a single array of them.
+fn num_list(name: &str, args: Vec<Value>) -> Result<Vec<f64>, String> {
+    check_arg_count(name, &args, 1, usize::MAX)?;
+    let values = match args.first() {
+        Some(&Value::Array(_)) if args.len() == 1 => Rc::unwrap_or_clone(expect_array(name, args.into_iter().next().unwrap())?),
+        _ => args,
+    };
+
+    if values.is_empty() {
+        return Err(format!("{}: no values given", name));
+    }
+    values.iter().map(|v| expect_num(name, v)).collect()
+}
+
+pub fn min(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let nums = num_list("min", args)?;
+    Ok(Value::Num(nums.into_iter().fold(f64::INFINITY, f64::min)))
+}
+
+pub fn max(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    let nums = num_list("max", args)?;
+    Ok(Value::Num(nums.into_iter().fold(f64::NEG_INFINITY, f64::max)))
+}
+
+pub fn clamp(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("clamp", &args, 3, 3)?;
+    let x = expect_num("clamp", &args[0])?;
+    let lo = expect_num("clamp", &args[1])?;
+    let hi = expect_num("clamp", &args[2])?;
+
+    if lo > hi {
+        return Err(format!("clamp: lower bound {} is greater than upper bound {}", lo, hi));
+    }
+    Ok(Value::Num(x.max(lo).min(hi)))
+}
+
+/// `[a // b, a - b * (a // b)]`, so the remainder has the sign of `b`.
+pub fn divmod(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("divmod", &args, 2, 2)?;
+    for arg in &args {
+        if !number::is_number(arg) {
+            return Err(format!("divmod: expected num, found {}", arg.get_type()));
+        }
+    }
+
+    let quotient = number::arithmetic(&Op::FloorDiv, &args[0], &args[1]).map_err(|e| format!("divmod: {}", e))?;
+    let remainder = number::arithmetic(&Op::Mul, &args[1], &quotient)
+        .and_then(|product| number::arithmetic(&Op::Sub, &args[0], &product))
+        .map_err(|e| format!("divmod: {}", e))?;
+    Ok(Value::array(vec![quotient, remainder]))
+}
+
+// splitmix64: tiny, fast, and good enough for simulations (not for cryptography).
+thread_local! {
+    static RNG_STATE: Cell<u64> = Cell::new(time_seed());
+}
+
+fn time_seed() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() ^ (d.subsec_nanos() as u64) << 32,
+        Err(_) => 0,
+    }
+}
+
+/// Makes the sequence of random numbers repeatable.
+pub fn seed_random(seed: u64) {
+    RNG_STATE.with(|state| state.set(seed));
+}
+
+fn next_u64() -> u64 {
+    RNG_STATE.with(|state| {
+        let next = state.get().wrapping_add(0x9E3779B97F4A7C15);
+        state.set(next);
+
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    })
+}
+
+// A uniformly distributed number in 0..bound, without modulo bias.
+fn next_below(bound: u64) -> u64 {
+    let zone = u64::MAX - u64::MAX % bound;
+    loop {
+        let n = next_u64();
+        if n < zone {
+            return n % bound;
+        }
+    }
+}
+
+/// A number in [0, 1)
+pub fn random(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("random", &args, 0, 0)?;
+    Ok(Value::Num((next_u64() >> 11) as f64 / (1u64 << 53) as f64))
+}
+
+// An int, or a num that's a whole number small enough to be exact.
+fn int_bound(value: &Value) -> Option<i64> {
+    match *value {
+        Value::Int(i) => Some(i),
+        _ => value.as_float().filter(|n| n.fract() == 0.0 && n.abs() <= 2f64.powi(53)).map(|n| n as i64),
+    }
+}
+
+/// An integer in [lo, hi], including both bounds.
+pub fn random_int(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("random_int", &args, 2, 2)?;
+    expect_num("random_int", &args[0])?;
+    expect_num("random_int", &args[1])?;
+    let (lo, hi) = match (int_bound(&args[0]), int_bound(&args[1])) {
+        (Some(lo), Some(hi)) => (lo, hi),
+        _ => return Err(format!("random_int: bounds must be integers, found {} and {}", args[0], args[1])),
+    };
+    if lo > hi {
+        return Err(format!("random_int: lower bound {} is greater than upper bound {}", lo, hi));
+    }
+
+    // How far above `lo` the result can be, which fits in a u64 even for the whole range of ints
+    let span = hi.wrapping_sub(lo) as u64;
+    let offset = if span == u64::MAX { next_u64() } else { next_below(span + 1) };
+    Ok(Value::Int(lo.wrapping_add(offset as i64)))
+}
+
+pub fn shuffle(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("shuffle", &args, 1, 1)?;
+    let mut vec = expect_array("shuffle", args.into_iter().next().unwrap())?;
+    for i in (1..vec.len()).rev() {
+        let j = next_below(i as u64 + 1) as usize;
+        Rc::make_mut(&mut vec).swap(i, j);
+    }
+    Ok(changed_array(vec))
+}
+
+pub fn choice(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("choice", &args, 1, 1)?;
+    let vec = expect_array("choice", args.into_iter().next().unwrap())?;
+    if vec.is_empty() {
+        return Err("choice: array is empty".to_string());
+    }
+    let i = next_below(vec.len() as u64) as usize;
+    Ok(vec[i].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use env::Env;
+
+    fn call(f: Builtin, args: Vec<Value>) -> Result<Value, String> {
+        f(&mut Context::new(&mut |_, _| unreachable!(), &[], &Env::default()), args)
+    }
+
+    #[test]
+    fn seeded_sequence_repeats() {
+        seed_random(42);
+        let first = (0..5).map(|_| call(random, vec![])).collect::<Vec<_>>();
+        seed_random(42);
+        let second = (0..5).map(|_| call(random, vec![])).collect::<Vec<_>>();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn random_int_in_bounds() {
+        for _ in 0..1000 {
+            match call(random_int, vec![Value::Num(-2.0), Value::Num(2.0)]) {
+                Ok(Value::Int(n)) => assert!((-2..=2).contains(&n)),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            match call(random_int, vec![Value::Int(i64::MAX - 1), Value::Int(i64::MAX)]) {
+                Ok(Value::Int(n)) => assert!(n >= i64::MAX - 1),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        assert!(call(random_int, vec![Value::Int(0), Value::Int(i64::MAX)]).is_ok());
+        assert!(call(random_int, vec![Value::Int(i64::MIN), Value::Int(i64::MAX)]).is_ok());
+        assert_eq!(
+            call(random_int, vec![Value::Int(0), Value::Num(1.5)]),
+            Err("random_int: bounds must be integers, found 0 and 1.5".to_string())
+        );
+    }
+
+    #[test]
+    fn min_max_accept_arrays() {
+        let nums = Value::array(vec![Value::Num(3.0), Value::Num(-1.0)]);
+        assert_eq!(call(min, vec![nums.clone()]), Ok(Value::Num(-1.0)));
+        assert_eq!(call(max, vec![Value::Num(3.0), Value::Num(-1.0)]), Ok(Value::Num(3.0)));
+        assert!(call(max, vec![Value::array(vec![])]).is_err());
+    }
+}
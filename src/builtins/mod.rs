@@ -0,0 +1,343 @@
+use ast::*;
+use env::{Closure, Env};
+use limits;
+use number;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ptr;
+use std::rc::Rc;
+
+mod array;
+mod dict;
+mod format;
+mod fs;
+mod higher_order;
+mod json;
+mod math;
+mod process;
+mod regex;
+mod string;
+mod time;
+
+pub use self::format::format_string;
+pub use self::math::seed_random;
+
+/// Calls a script function value with already-evaluated arguments.
+pub type Caller<'a> = dyn FnMut(Value, Vec<Value>) -> Result<Value, String> + 'a;
+
+pub type Builtin = fn(&mut Context, Vec<Value>) -> Result<Value, String>;
+
+/// What a builtin knows about the call it was invoked from.
+pub struct Context<'a> {
+    caller: &'a mut Caller<'a>,
+    arg_names: &'a [Option<String>],
+    vars: &'a Env,
+}
+
+impl<'a> Context<'a> {
+    /// `arg_names` holds the variable name of each argument that was passed as a plain reference,
+    /// and `vars` the variables visible at the call.
+    pub fn new(caller: &'a mut Caller<'a>, arg_names: &'a [Option<String>], vars: &'a Env) -> Self {
+        Context {
+            caller: caller,
+            arg_names: arg_names,
+            vars: vars,
+        }
+    }
+
+    /// Calls `func`, which was passed to builtin `name` as argument number `arg`.
+    /// Errors are prefixed with the builtin and the callback that failed.
+    pub fn call(&mut self, name: &str, arg: usize, func: &Value, args: Vec<Value>) -> Result<Value, String> {
+        match (self.caller)(func.clone(), args) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                let callback = match self.arg_names.get(arg) {
+                    Some(&Some(ref var)) => var.clone(),
+                    _ => func.to_string(),
+                };
+                Err(format!("{}: error in callback {}: {}", name, callback, e))
+            },
+        }
+    }
+}
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "push" => array::push,
+        "pop" => array::pop,
+        "last" => array::last,
+        "insert" => insert,
+        "remove" => remove,
+        "concat" => array::concat,
+        "slice" => array::slice,
+        "reverse" => array::reverse,
+        "contains" => contains,
+        "index_of" => array::index_of,
+        "sort" => array::sort,
+
+        "map" => higher_order::map,
+        "filter" => higher_order::filter,
+        "reduce" => higher_order::reduce,
+        "fold" => higher_order::fold,
+        "any" => higher_order::any,
+        "all" => higher_order::all,
+        "find" => find,
+        "zip" => higher_order::zip,
+        "enumerate" => higher_order::enumerate,
+        "flat_map" => higher_order::flat_map,
+        "group_by" => higher_order::group_by,
+
+        "split" => string::split,
+        "join" => string::join,
+        "trim" => string::trim,
+        "trim_start" => string::trim_start,
+        "trim_end" => string::trim_end,
+        "replace" => string::replace,
+        "starts_with" => string::starts_with,
+        "ends_with" => string::ends_with,
+        "substring" => string::substring,
+        "repeat" => string::repeat,
+        "pad_left" => string::pad_left,
+        "pad_right" => string::pad_right,
+        "chars" => string::chars,
+        "char_code" => string::char_code,
+        "from_char_code" => string::from_char_code,
+        "format" => format::format,
+
+        "keys" => dict::keys,
+        "values" => dict::values,
+
+        "json_parse" => json::json_parse,
+        "json_stringify" => json::json_stringify,
+
+        "sqrt" => math::sqrt,
+        "abs" => math::abs,
+        "floor" => math::floor,
+        "ceil" => math::ceil,
+        "round" => math::round,
+        "trunc" => math::trunc,
+        "sign" => math::sign,
+        "min" => math::min,
+        "max" => math::max,
+        "clamp" => math::clamp,
+        "divmod" => math::divmod,
+        "sin" => math::sin,
+        "cos" => math::cos,
+        "tan" => math::tan,
+        "asin" => math::asin,
+        "acos" => math::acos,
+        "atan" => math::atan,
+        "atan2" => math::atan2,
+        "exp" => math::exp,
+        "log" => math::log,
+        "log10" => math::log10,
+        "log2" => math::log2,
+        "random" => math::random,
+        "random_int" => math::random_int,
+        "shuffle" => math::shuffle,
+        "choice" => math::choice,
+
+        "read_file" => fs::read_file,
+        "write_file" => fs::write_file,
+        "append_file" => fs::append_file,
+        "exists" => fs::exists,
+        "is_dir" => fs::is_dir,
+        "list_dir" => fs::list_dir,
+        "mkdir" => fs::mkdir,
+        "remove_file" => fs::remove_file,
+        "rename" => fs::rename,
+        "stat" => fs::stat,
+
+        "getenv" => process::getenv,
+        "setenv" => process::setenv,
+        "env_vars" => process::env_vars,
+        "cwd" => process::cwd,
+        "chdir" => process::chdir,
+        "run" => process::run,
+
+        "matches" => regex::matches,
+        "find_all" => regex::find_all,
+        "captures" => regex::captures,
+        "replace_all" => regex::replace_all,
+
+        "now" => time::now,
+        "clock" => time::clock,
+        "sleep" => time::sleep,
+        "date" => time::date,
+        "timestamp" => time::timestamp,
+        "format_time" => time::format_time,
+        "parse_time" => time::parse_time,
+        _ => return None,
+    };
+
+    Some(f)
+}
+
+/// Variables that are defined before a script starts.
+pub fn constants() -> Vec<(&'static str, Value)> {
+    math::constants()
+}
+
+// Some names are shared between types: `contains` and `find` work on arrays
+// and strings, and `insert`, `remove` and `contains` on arrays and dicts.
+
+fn insert(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    match args.first() {
+        Some(&Value::Dict(_)) => dict::insert(ctx, args),
+        _ => array::insert(ctx, args),
+    }
+}
+
+fn remove(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    match args.first() {
+        Some(&Value::Dict(_)) => dict::remove(ctx, args),
+        _ => array::remove(ctx, args),
+    }
+}
+
+fn contains(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    match args.first() {
+        Some(&Value::String(_)) => string::contains(ctx, args),
+        Some(&Value::Dict(_)) => dict::contains(ctx, args),
+        _ => array::contains(ctx, args),
+    }
+}
+
+fn find(ctx: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    match args.first() {
+        Some(&Value::String(_)) => string::find(ctx, args),
+        _ => higher_order::find(ctx, args),
+    }
+}
+
+pub fn check_arg_count(name: &str, args: &[Value], min: usize, max: usize) -> Result<(), String> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(format!("wrong number of arguments passed to {} (expected {}, found {})", name, expected, args.len()));
+    }
+
+    Ok(())
+}
+
+pub fn expect_num(name: &str, value: &Value) -> Result<f64, String> {
+    match value.as_float() {
+        Some(n) => Ok(n),
+        None => Err(format!("{}: expected num, found {}", name, value.get_type())),
+    }
+}
+
+pub fn expect_dict(name: &str, value: Value) -> Result<BTreeMap<String, Value>, String> {
+    match value {
+        Value::Dict(map) => Ok(map),
+        _ => Err(format!("{}: expected dict, found {}", name, value.get_type())),
+    }
+}
+
+pub fn expect_string<'v>(name: &str, value: &'v Value) -> Result<&'v str, String> {
+    match *value {
+        Value::String(ref s) => Ok(s),
+        _ => Err(format!("{}: expected string, found {}", name, value.get_type())),
+    }
+}
+
+pub fn expect_index(name: &str, value: &Value) -> Result<usize, String> {
+    if let Value::Int(i) = *value {
+        if i < 0 {
+            return Err(format!("{}: {} cannot be used as index", name, i));
+        }
+        return Ok(i as usize);
+    }
+
+    let n = expect_num(name, value)?;
+    if n < 0.0 || n.fract() != 0.0 {
+        return Err(format!("{}: {} cannot be used as index", name, n));
+    }
+
+    Ok(n as usize)
+}
+
+/// The array's items, still shared with anything else that has them.
+/// `Rc::make_mut` copies them before a change if they are shared.
+pub fn expect_array(name: &str, value: Value) -> Result<Rc<Vec<Value>>, String> {
+    match value {
+        Value::Array(vec) => Ok(vec),
+        _ => Err(format!("{}: expected array, found {}", name, value.get_type())),
+    }
+}
+
+/// An array from `expect_array` once it's been changed, which may have copied it.
+pub fn changed_array(vec: Rc<Vec<Value>>) -> Value {
+    let value = Value::Array(vec);
+    limits::track(&value);
+    value
+}
+
+// Values of different types are ordered by type, so that sorting a mixed array
+// always gives the same result regardless of the initial order.
+fn type_rank(value: &Value) -> u8 {
+    match *value {
+        Value::Void => 0,
+        Value::Boolean(_) => 1,
+        Value::Num(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Dict(_) => 5,
+        Value::Type(_) => 6,
+        Value::Func(_, _) => 7,
+    }
+}
+
+/// Total ordering over values, used by `sort` and friends.
+pub fn compare_values(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (&Value::Num(n1), &Value::Num(n2)) => {
+            // NaN sorts after every other number
+            n1.partial_cmp(&n2).unwrap_or_else(|| n1.is_nan().cmp(&n2.is_nan()))
+        },
+        _ if number::is_number(left) && number::is_number(right) => {
+            match number::compare(left, right) {
+                Ok(Some(ordering)) => ordering,
+                // Decimals and nums can't normally be compared, but sorting needs some order
+                _ => compare_values(&Value::Num(left.as_float().unwrap()), &Value::Num(right.as_float().unwrap())),
+            }
+        },
+        (&Value::String(ref s1), &Value::String(ref s2)) => s1.cmp(s2),
+        (&Value::Boolean(b1), &Value::Boolean(b2)) => b1.cmp(&b2),
+        (&Value::Array(ref a1), &Value::Array(ref a2)) => {
+            for (v1, v2) in a1.iter().zip(a2.iter()) {
+                match compare_values(v1, v2) {
+                    Ordering::Equal => {},
+                    other => return other,
+                }
+            }
+            a1.len().cmp(&a2.len())
+        },
+        (&Value::Dict(ref d1), &Value::Dict(ref d2)) => {
+            for ((k1, v1), (k2, v2)) in d1.iter().zip(d2.iter()) {
+                match k1.cmp(k2).then_with(|| compare_values(v1, v2)) {
+                    Ordering::Equal => {},
+                    other => return other,
+                }
+            }
+            d1.len().cmp(&d2.len())
+        },
+        (&Value::Type(ref t1), &Value::Type(ref t2)) => t1.to_string().cmp(&t2.to_string()),
+        // A function is only equal to itself; otherwise the order is arbitrary, but stays the same
+        (&Value::Func(ref c1, ref f1), &Value::Func(ref c2, ref f2)) => {
+            let closure = |c: &Option<Rc<Closure>>| c.as_ref().map_or(ptr::null(), Rc::as_ptr);
+            Rc::as_ptr(f1).cmp(&Rc::as_ptr(f2)).then_with(|| closure(c1).cmp(&closure(c2)))
+        },
+        _ => type_rank(left).cmp(&type_rank(right)),
+    }
+}
+
+/// Whether `compare_values` puts two values in the same place. `contains`,
+/// `index_of` and `group_by` all look for a value this way.
+pub fn values_equal(left: &Value, right: &Value) -> bool {
+    compare_values(left, right) == Ordering::Equal
+}
@@ -0,0 +1,149 @@
+use super::*;
+
+use std::cmp;
+use std::env;
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Returns `void` if the variable isn't set.
+pub fn getenv(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("getenv", &args, 1, 1)?;
+    let name = expect_string("getenv", &args[0])?;
+    match env::var_os(name) {
+        Some(value) => Ok(Value::string(value.to_string_lossy().into_owned())),
+        None => Ok(Value::Void),
+    }
+}
+
+pub fn setenv(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("setenv", &args, 2, 2)?;
+    let name = expect_string("setenv", &args[0])?;
+    let value = expect_string("setenv", &args[1])?;
+
+    if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0') {
+        return Err(format!("setenv: invalid environment variable: {}", name));
+    }
+    env::set_var(name, value);
+    Ok(Value::Void)
+}
+
+/// Returns `[name, value]` pairs, sorted by name.
+pub fn env_vars(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("env_vars", &args, 0, 0)?;
+    let mut vars = env::vars_os()
+        .map(|(k, v)| (k.to_string_lossy().into_owned(), v.to_string_lossy().into_owned()))
+        .collect::<Vec<_>>();
+    vars.sort();
+
+    let pairs = vars.into_iter()
+        .map(|(k, v)| Value::array(vec![Value::string(k), Value::string(v)]))
+        .collect();
+    Ok(Value::array(pairs))
+}
+
+pub fn cwd(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("cwd", &args, 0, 0)?;
+    env::current_dir()
+        .map(|p| Value::string(p.to_string_lossy().into_owned()))
+        .map_err(|e| format!("cwd: {}", e))
+}
+
+pub fn chdir(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("chdir", &args, 1, 1)?;
+    let path = expect_string("chdir", &args[0])?;
+    env::set_current_dir(path)
+        .map(|_| Value::Void)
+        .map_err(|e| format!("chdir: {}: {}", path, e))
+}
+
+/// `run(cmd, args, stdin)` runs a program and waits for it to finish, returning
+/// `[status, stdout, stderr]`. `status` is `void` if the program was killed by a signal.
+pub fn run(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("run", &args, 1, 3)?;
+    let cmd = expect_string("run", &args[0])?;
+    let cmd_args = match args.get(1) {
+        Some(a) => expect_array("run", a.clone())?
+            .iter()
+            .map(|v| expect_string("run", v).map(|s| s.to_string()))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+    let input = match args.get(2) {
+        Some(s) => Some(expect_string("run", s)?),
+        None => None,
+    };
+
+    let mut child = Command::new(cmd)
+        .args(&cmd_args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("run: {}: {}", cmd, e))?;
+
+    if let Some(input) = input {
+        // Written from another thread, so that a child which fills its output
+        // pipe before reading all of its input can't deadlock with us.
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_string();
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+
+    let room = limits::room();
+    let stdout = read_output(child.stdout.take().unwrap(), room);
+    let stderr = read_output(child.stderr.take().unwrap(), room);
+    let status = wait(&mut child, cmd)?;
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    // Output that didn't fit is always over a limit
+    if let Some(room) = room {
+        let len = cmp::max(stdout.len(), stderr.len());
+        if len > room {
+            limits::check(len, len)?;
+        }
+    }
+
+    let status = match status.code() {
+        Some(code) => Value::Int(code as i64),
+        None => Value::Void,
+    };
+    Ok(Value::array(vec![
+        status,
+        Value::string(String::from_utf8_lossy(&stdout).into_owned()),
+        Value::string(String::from_utf8_lossy(&stderr).into_owned()),
+    ]))
+}
+
+// Reads what a program writes to one of its pipes on another thread, stopping
+// one byte after `max`. Closing the pipe then stops most programs that carry
+// on writing, as it does for `yes | head`.
+fn read_output<R: Read + Send + 'static>(pipe: R, max: Option<usize>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = pipe.take(max.map_or(u64::MAX, |max| max as u64 + 1)).read_to_end(&mut output);
+        output
+    })
+}
+
+// Waits for a program to finish, killing it if the script runs out of time
+// first. It's checked on more and more rarely, so a quick program doesn't
+// keep the script waiting and a slow one doesn't keep it busy.
+fn wait(child: &mut Child, cmd: &str) -> Result<ExitStatus, String> {
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("run: {}: {}", cmd, e))? {
+            return Ok(status);
+        }
+        if let Err(e) = limits::sleep(interval) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        interval = cmp::min(interval * 2, Duration::from_millis(50));
+    }
+}
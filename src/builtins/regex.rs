@@ -0,0 +1,815 @@
+use super::*;
+
+use std::char;
+
+// A small regular expression engine. Patterns support literals, `.`, classes
+// like `[a-z_]` and `[^0-9]`, the escapes `\d \w \s` (and their negations
+// `\D \W \S`), anchors `^ $ \b \B`, groups `(...)`, `(?:...)` and
+// `(?P<name>...)`, alternation `|`, and the quantifiers `* + ? {n} {n,} {n,m}`,
+// each of which can be made lazy with a trailing `?`.
+//
+// Patterns compile to a small instruction set that runs on a backtracking
+// matcher. The matcher remembers which (instruction, position) pairs have
+// already failed, so matching takes at most O(pattern × text) steps instead
+// of blowing up on patterns like `(a*)*b`.
+
+const MAX_PROGRAM_LEN: usize = 10000;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool), // (ranges, negated)
+    Start,
+    End,
+    WordBoundary(bool), // false for \B
+    Group(Box<Node>, Option<usize>), // capture index, if capturing
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>, bool), // (node, min, max, greedy)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    names: Vec<(String, usize)>,
+}
+
+fn word_ranges() -> Vec<(char, char)> {
+    vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')]
+}
+
+fn digit_ranges() -> Vec<(char, char)> {
+    vec![('0', '9')]
+}
+
+fn space_ranges() -> Vec<(char, char)> {
+    vec![('\t', '\r'), (' ', ' ')]
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The ranges of characters not covered by `ranges`.
+fn complement(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort();
+
+    let mut result = Vec::new();
+    let mut next = 0u32;
+    for (lo, hi) in sorted {
+        if (lo as u32) > next {
+            push_range(&mut result, next, lo as u32 - 1);
+        }
+        next = next.max(hi as u32 + 1);
+    }
+    push_range(&mut result, next, char::MAX as u32);
+    result
+}
+
+// Adds a range of code points, skipping the surrogates, which aren't chars.
+fn push_range(ranges: &mut Vec<(char, char)>, lo: u32, hi: u32) {
+    let mut add = |lo: u32, hi: u32| {
+        if lo <= hi {
+            if let (Some(lo), Some(hi)) = (char::from_u32(lo), char::from_u32(hi)) {
+                ranges.push((lo, hi));
+            }
+        }
+    };
+    if lo < 0xD800 && hi > 0xDFFF {
+        add(lo, 0xD7FF);
+        add(0xE000, hi);
+    } else {
+        add(lo, hi);
+    }
+}
+
+impl Parser {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("{} at position {}", message, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.pos += 1;
+                let min = match self.parse_number() {
+                    Some(n) => n,
+                    None => return self.error("expected number in repetition"),
+                };
+                let max = if self.eat(',') {
+                    if self.peek() == Some('}') { None } else { self.parse_number() }
+                } else {
+                    Some(min)
+                };
+                if self.peek() != Some('}') {
+                    return self.error("unclosed repetition");
+                }
+                if max.map_or(false, |max| max < min) {
+                    return self.error("repetition maximum is less than minimum");
+                }
+                (min, max)
+            },
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+
+        match atom {
+            Node::Empty | Node::Start | Node::End | Node::WordBoundary(_) => {
+                self.pos = start;
+                return self.error("nothing to repeat");
+            },
+            _ => {},
+        }
+
+        let greedy = !self.eat('?');
+        let node = Node::Repeat(Box::new(atom), min, max, greedy);
+        match self.peek() {
+            Some('*') | Some('+') | Some('?') => self.error("repeated quantifier"),
+            _ => Ok(node),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, String> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error("pattern ends with '\\'"),
+        };
+        self.pos += 1;
+
+        Ok(match c {
+            'd' => Node::Class(digit_ranges(), false),
+            'D' => Node::Class(digit_ranges(), true),
+            'w' => Node::Class(word_ranges(), false),
+            'W' => Node::Class(word_ranges(), true),
+            's' => Node::Class(space_ranges(), false),
+            'S' => Node::Class(space_ranges(), true),
+            'b' => Node::WordBoundary(true),
+            'B' => Node::WordBoundary(false),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            'r' => Node::Char('\r'),
+            c if c.is_ascii_alphanumeric() => {
+                self.pos -= 1;
+                return self.error(&format!("unknown escape '\\{}'", c));
+            },
+            c => Node::Char(c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error("unclosed character class"),
+            };
+            self.pos += 1;
+
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                match self.parse_escape()? {
+                    Node::Char(c) => c,
+                    Node::Class(r, false) => {
+                        ranges.extend(r);
+                        continue;
+                    },
+                    Node::Class(r, true) => {
+                        ranges.extend(complement(&r));
+                        continue;
+                    },
+                    _ => return self.error("invalid escape in character class"),
+                }
+            } else {
+                c
+            };
+
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).map_or(false, |&c| c != ']') {
+                self.pos += 1;
+                let hi = match self.peek() {
+                    Some('\\') => {
+                        self.pos += 1;
+                        match self.parse_escape()? {
+                            Node::Char(c) => c,
+                            _ => return self.error("invalid range in character class"),
+                        }
+                    },
+                    Some(c) => {
+                        self.pos += 1;
+                        c
+                    },
+                    None => return self.error("unclosed character class"),
+                };
+                if hi < lo {
+                    return self.error(&format!("invalid range {}-{} in character class", lo, hi));
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+
+        Ok(Node::Class(ranges, negated))
+    }
+
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let open = self.pos - 1;
+        let index = if self.eat('?') {
+            if self.eat(':') {
+                None
+            } else if self.eat('P') && self.eat('<') || self.eat('<') {
+                let start = self.pos;
+                while self.peek().map_or(false, is_word_char) {
+                    self.pos += 1;
+                }
+                let name = self.chars[start..self.pos].iter().collect::<String>();
+                if name.is_empty() || !self.eat('>') {
+                    return self.error("invalid group name");
+                }
+                if self.names.iter().any(|n| n.0 == name) {
+                    return self.error(&format!("duplicate group name '{}'", name));
+                }
+
+                self.groups += 1;
+                self.names.push((name, self.groups));
+                Some(self.groups)
+            } else {
+                return self.error("unknown group flag");
+            }
+        } else {
+            self.groups += 1;
+            Some(self.groups)
+        };
+
+        let inner = self.parse_alternation()?;
+        if !self.eat(')') {
+            self.pos = open;
+            return self.error("unclosed group");
+        }
+        Ok(Node::Group(Box::new(inner), index))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().unwrap();
+        self.pos += 1;
+
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '\\' => self.parse_escape(),
+            '[' => self.parse_class(),
+            '(' => self.parse_group(),
+            '*' | '+' | '?' => {
+                self.pos -= 1;
+                self.error("nothing to repeat")
+            },
+            c => Ok(Node::Char(c)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    WordBoundary(bool),
+    Save(usize),
+    Split(usize, usize), // try the first branch, then the second
+    Jump(usize),
+    Match,
+}
+
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize, // not counting group 0, the whole match
+    names: Vec<(String, usize)>,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM_LEN {
+            return Err("pattern is too large".to_string());
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        match *node {
+            Node::Empty => {},
+            Node::Char(c) => { self.emit(Inst::Char(c))?; },
+            Node::Any => { self.emit(Inst::Any)?; },
+            Node::Class(ref ranges, negated) => { self.emit(Inst::Class(ranges.clone(), negated))?; },
+            Node::Start => { self.emit(Inst::Start)?; },
+            Node::End => { self.emit(Inst::End)?; },
+            Node::WordBoundary(b) => { self.emit(Inst::WordBoundary(b))?; },
+            Node::Group(ref inner, index) => {
+                if let Some(i) = index {
+                    self.emit(Inst::Save(i * 2))?;
+                    self.compile(inner)?;
+                    self.emit(Inst::Save(i * 2 + 1))?;
+                } else {
+                    self.compile(inner)?;
+                }
+            },
+            Node::Concat(ref nodes) => {
+                for n in nodes {
+                    self.compile(n)?;
+                }
+            },
+            Node::Alternate(ref branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(branch)?;
+                        jumps.push(self.emit(Inst::Jump(0))?);
+                        let next = self.program.len();
+                        self.program[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.compile(branch)?;
+                    }
+                }
+                let end = self.program.len();
+                for j in jumps {
+                    self.program[j] = Inst::Jump(end);
+                }
+            },
+            Node::Repeat(ref inner, min, max, greedy) => {
+                for _ in 0..min {
+                    self.compile(inner)?;
+                }
+
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(inner)?;
+                        self.emit(Inst::Jump(split))?;
+                        let end = self.program.len();
+                        self.program[split] = self.split(split + 1, end, greedy);
+                    },
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in min..max {
+                            splits.push(self.emit(Inst::Split(0, 0))?);
+                            self.compile(inner)?;
+                        }
+                        let end = self.program.len();
+                        for s in splits {
+                            self.program[s] = self.split(s + 1, end, greedy);
+                        }
+                    },
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn split(&self, body: usize, skip: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(body, skip)
+        } else {
+            Inst::Split(skip, body)
+        }
+    }
+}
+
+enum Job {
+    Explore(usize, usize), // (pc, pos)
+    Restore(usize, Option<usize>), // (slot, old value)
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+            names: Vec::new(),
+        };
+
+        let node = parser.parse_alternation()?;
+        if parser.pos < parser.chars.len() {
+            return parser.error("unmatched ')'");
+        }
+
+        let mut compiler = Compiler { program: Vec::new() };
+        compiler.emit(Inst::Save(0))?;
+        compiler.compile(&node)?;
+        compiler.emit(Inst::Save(1))?;
+        compiler.emit(Inst::Match)?;
+
+        Ok(Regex {
+            program: compiler.program,
+            groups: parser.groups,
+            names: parser.names,
+        })
+    }
+
+    /// Finds the leftmost match starting at or after `start`, returning the
+    /// char positions of each group.
+    fn search(&self, text: &[char], start: usize) -> Option<Vec<Option<usize>>> {
+        let mut visited = vec![false; self.program.len() * (text.len() + 1)];
+        let mut slots = vec![None; (self.groups + 1) * 2];
+
+        for pos in start..text.len() + 1 {
+            if self.run(text, pos, &mut visited, &mut slots) {
+                return Some(slots);
+            }
+        }
+        None
+    }
+
+    fn run(&self, text: &[char], start: usize, visited: &mut [bool], slots: &mut [Option<usize>]) -> bool {
+        let mut stack = vec![Job::Explore(0, start)];
+
+        while let Some(job) = stack.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Explore(pc, pos) => (pc, pos),
+                Job::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                },
+            };
+
+            loop {
+                // A state that was reached before can't lead to a match now either
+                let key = pc * (text.len() + 1) + pos;
+                if visited[key] {
+                    break;
+                }
+                visited[key] = true;
+
+                match self.program[pc] {
+                    Inst::Char(c) => {
+                        if text.get(pos) != Some(&c) {
+                            break;
+                        }
+                        pc += 1;
+                        pos += 1;
+                    },
+                    Inst::Any => {
+                        if pos >= text.len() {
+                            break;
+                        }
+                        pc += 1;
+                        pos += 1;
+                    },
+                    Inst::Class(ref ranges, negated) => {
+                        match text.get(pos) {
+                            Some(&c) if ranges.iter().any(|r| r.0 <= c && c <= r.1) != negated => {
+                                pc += 1;
+                                pos += 1;
+                            },
+                            _ => break,
+                        }
+                    },
+                    Inst::Start => {
+                        if pos != 0 {
+                            break;
+                        }
+                        pc += 1;
+                    },
+                    Inst::End => {
+                        if pos != text.len() {
+                            break;
+                        }
+                        pc += 1;
+                    },
+                    Inst::WordBoundary(expected) => {
+                        let before = pos > 0 && is_word_char(text[pos - 1]);
+                        let after = pos < text.len() && is_word_char(text[pos]);
+                        if (before != after) != expected {
+                            break;
+                        }
+                        pc += 1;
+                    },
+                    Inst::Save(slot) => {
+                        stack.push(Job::Restore(slot, slots[slot]));
+                        slots[slot] = Some(pos);
+                        pc += 1;
+                    },
+                    Inst::Split(first, second) => {
+                        stack.push(Job::Explore(second, pos));
+                        pc = first;
+                    },
+                    Inst::Jump(target) => pc = target,
+                    Inst::Match => return true,
+                }
+            }
+        }
+
+        false
+    }
+
+    fn group_index(&self, name: &str) -> Option<usize> {
+        match name.parse::<usize>() {
+            Ok(i) if i <= self.groups => Some(i),
+            Ok(_) => None,
+            Err(_) => self.names.iter().find(|n| n.0 == name).map(|n| n.1),
+        }
+    }
+}
+
+struct Match {
+    slots: Vec<Option<usize>>,
+}
+
+impl Match {
+    fn range(&self, group: usize) -> Option<(usize, usize)> {
+        match (self.slots[group * 2], self.slots[group * 2 + 1]) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    fn group(&self, text: &[char], group: usize) -> Option<String> {
+        self.range(group).map(|(start, end)| text[start..end].iter().collect())
+    }
+}
+
+// Every non-overlapping match, from left to right.
+fn find_matches(regex: &Regex, text: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+
+    while start <= text.len() {
+        let slots = match regex.search(text, start) {
+            Some(slots) => slots,
+            None => break,
+        };
+        let m = Match { slots: slots };
+        let (match_start, match_end) = m.range(0).unwrap();
+
+        // An empty match can't be followed by another one in the same place
+        start = if match_end == match_start { match_end + 1 } else { match_end };
+        matches.push(m);
+    }
+
+    matches
+}
+
+// Expands `$1`, `${1}`, `${name}` and `$$` in a replacement string.
+fn expand(regex: &Regex, m: &Match, text: &[char], replacement: &str, out: &mut String) -> Result<(), String> {
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+
+        let name = match chars.peek().cloned() {
+            Some('$') => {
+                chars.next();
+                out.push('$');
+                continue;
+            },
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err("unclosed '${' in replacement".to_string()),
+                    }
+                }
+                name
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let mut name = String::new();
+                while let Some(c) = chars.peek().cloned() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                name
+            },
+            _ => return Err("'$' in replacement must be followed by a group, or written as '$$'".to_string()),
+        };
+
+        match regex.group_index(&name) {
+            Some(i) => out.push_str(&m.group(text, i).unwrap_or_default()),
+            None => return Err(format!("replacement refers to unknown group '{}'", name)),
+        }
+    }
+
+    Ok(())
+}
+
+fn compile_arg(name: &str, pattern: &Value) -> Result<Regex, String> {
+    let pattern = expect_string(name, pattern)?;
+    Regex::new(pattern).map_err(|e| format!("{}: invalid regex \"{}\": {}", name, pattern, e))
+}
+
+/// Whether the pattern matches anywhere in the string; use `^` and `$` to match all of it.
+pub fn matches(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("matches", &args, 2, 2)?;
+    let text = expect_string("matches", &args[0])?.chars().collect::<Vec<_>>();
+    let regex = compile_arg("matches", &args[1])?;
+    Ok(Value::Boolean(regex.search(&text, 0).is_some()))
+}
+
+pub fn find_all(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("find_all", &args, 2, 2)?;
+    let text = expect_string("find_all", &args[0])?.chars().collect::<Vec<_>>();
+    let regex = compile_arg("find_all", &args[1])?;
+
+    let found = find_matches(&regex, &text)
+        .iter()
+        .map(|m| Value::string(m.group(&text, 0).unwrap()))
+        .collect();
+    Ok(Value::array(found))
+}
+
+/// Returns `[whole match, group 1, ...]` for the first match, or `void` if
+/// there is none. Groups that didn't take part in the match are `void`.
+pub fn captures(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("captures", &args, 2, 2)?;
+    let text = expect_string("captures", &args[0])?.chars().collect::<Vec<_>>();
+    let regex = compile_arg("captures", &args[1])?;
+
+    match regex.search(&text, 0) {
+        Some(slots) => {
+            let m = Match { slots: slots };
+            let groups = (0..regex.groups + 1)
+                .map(|i| m.group(&text, i).map(Value::string).unwrap_or(Value::Void))
+                .collect();
+            Ok(Value::array(groups))
+        },
+        None => Ok(Value::Void),
+    }
+}
+
+pub fn replace_all(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("replace_all", &args, 3, 3)?;
+    let text = expect_string("replace_all", &args[0])?.chars().collect::<Vec<_>>();
+    let regex = compile_arg("replace_all", &args[1])?;
+    let replacement = expect_string("replace_all", &args[2])?;
+
+    let mut out = String::new();
+    let mut last = 0;
+    for m in find_matches(&regex, &text) {
+        let (start, end) = m.range(0).unwrap();
+        out.extend(&text[last..start]);
+        expand(&regex, &m, &text, replacement, &mut out).map_err(|e| format!("replace_all: {}", e))?;
+        last = end;
+    }
+    out.extend(&text[last..]);
+
+    Ok(Value::string(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        Regex::new(pattern).unwrap().search(&text, 0).is_some()
+    }
+
+    fn first(pattern: &str, text: &str) -> Vec<Option<String>> {
+        let text = text.chars().collect::<Vec<_>>();
+        let regex = Regex::new(pattern).unwrap();
+        let m = Match { slots: regex.search(&text, 0).unwrap() };
+        (0..regex.groups + 1).map(|i| m.group(&text, i)).collect()
+    }
+
+    fn replace(text: &str, pattern: &str, replacement: &str) -> Result<String, String> {
+        let text = text.chars().collect::<Vec<_>>();
+        let regex = Regex::new(pattern).unwrap();
+        let mut out = String::new();
+        let mut last = 0;
+        for m in find_matches(&regex, &text) {
+            let (start, end) = m.range(0).unwrap();
+            out.extend(&text[last..start]);
+            expand(&regex, &m, &text, replacement, &mut out)?;
+            last = end;
+        }
+        out.extend(&text[last..]);
+        Ok(out)
+    }
+
+    #[test]
+    fn basic_matching() {
+        assert!(is_match(r"^\d{3}-\d{4}$", "555-1234"));
+        assert!(!is_match(r"^\d{3}-\d{4}$", "555-12345"));
+        assert!(is_match(r"colou?r", "the color red"));
+        assert!(is_match(r"\bcat\b", "a cat sat"));
+        assert!(!is_match(r"\bcat\b", "concatenate"));
+        assert!(is_match(r"[^a-z]", "abc1"));
+        assert!(is_match(r"a.c", "aéc"));
+        assert!(is_match(r"(foo|bar)+baz", "foobarfoobaz"));
+    }
+
+    #[test]
+    fn greedy_and_lazy() {
+        assert_eq!(first(r"<(.+)>", "<a><b>")[1], Some("a><b".to_string()));
+        assert_eq!(first(r"<(.+?)>", "<a><b>")[1], Some("a".to_string()));
+        assert_eq!(first(r"(a)|(b)", "b"), vec![Some("b".to_string()), None, Some("b".to_string())]);
+    }
+
+    #[test]
+    fn pathological_pattern_is_fast() {
+        let text = "a".repeat(5000);
+        assert!(!is_match(r"(a*)*b", &text));
+    }
+
+    #[test]
+    fn replacement_groups() {
+        assert_eq!(replace("2021-03-04", r"(\d+)-(\d+)-(\d+)", "$3/$2/$1"), Ok("04/03/2021".to_string()));
+        assert_eq!(replace("key=value", r"(?P<k>\w+)=(?P<v>\w+)", "${v}=${k} $$"), Ok("value=key $".to_string()));
+        assert_eq!(replace("abc", r"x*", "-"), Ok("-a-b-c-".to_string()));
+        assert!(replace("abc", r"b", "$2").is_err());
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(Regex::new("(ab").err(), Some("unclosed group at position 0".to_string()));
+        assert!(Regex::new("ab)").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("[a-").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new(r"\q").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+        assert!(Regex::new("a**").is_err());
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use super::*;
+
+use std::char;
+use std::iter;
+
+// Positions and lengths are counted in Unicode scalar values (chars), the
+// same as `length` and `as array`, rather than in bytes.
+
+fn string_arg(name: &str, args: &[Value], i: usize) -> Result<String, String> {
+    match args.get(i) {
+        Some(v) => expect_string(name, v).map(|s| s.to_string()),
+        None => unreachable!(),
+    }
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+// Converts a char index into a byte offset, allowing the index one past the end.
+fn byte_offset(s: &str, index: usize) -> Option<usize> {
+    s.char_indices().map(|(i, _)| i).chain(iter::once(s.len())).nth(index)
+}
+
+pub fn split(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("split", &args, 2, 2)?;
+    let s = string_arg("split", &args, 0)?;
+    let sep = string_arg("split", &args, 1)?;
+
+    let parts = if sep.is_empty() {
+        s.chars().map(|c| Value::string(c.to_string())).collect()
+    } else {
+        s.split(&*sep).map(|part| Value::string(part.to_string())).collect()
+    };
+    Ok(Value::array(parts))
+}
+
+/// Elements that aren't strings are joined as they would be printed.
+pub fn join(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("join", &args, 1, 2)?;
+    let sep = match args.get(1) {
+        Some(_) => string_arg("join", &args, 1)?,
+        None => String::new(),
+    };
+    let vec = expect_array("join", args.into_iter().next().unwrap())?;
+
+    let strings = vec.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+    Ok(Value::string(strings.join(&sep)))
+}
+
+pub fn trim(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("trim", &args, 1, 1)?;
+    Ok(Value::string(string_arg("trim", &args, 0)?.trim().to_string()))
+}
+
+pub fn trim_start(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("trim_start", &args, 1, 1)?;
+    Ok(Value::string(string_arg("trim_start", &args, 0)?.trim_start().to_string()))
+}
+
+pub fn trim_end(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("trim_end", &args, 1, 1)?;
+    Ok(Value::string(string_arg("trim_end", &args, 0)?.trim_end().to_string()))
+}
+
+/// Replaces every occurrence of `from`.
+pub fn replace(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("replace", &args, 3, 3)?;
+    let s = string_arg("replace", &args, 0)?;
+    let from = string_arg("replace", &args, 1)?;
+    let to = string_arg("replace", &args, 2)?;
+
+    if from.is_empty() {
+        return Err("replace: cannot replace an empty string".to_string());
+    }
+    Ok(Value::string(s.replace(&*from, &to)))
+}
+
+pub fn contains(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("contains", &args, 2, 2)?;
+    let s = string_arg("contains", &args, 0)?;
+    let needle = string_arg("contains", &args, 1)?;
+    Ok(Value::Boolean(s.contains(&*needle)))
+}
+
+pub fn starts_with(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("starts_with", &args, 2, 2)?;
+    let s = string_arg("starts_with", &args, 0)?;
+    let prefix = string_arg("starts_with", &args, 1)?;
+    Ok(Value::Boolean(s.starts_with(&*prefix)))
+}
+
+pub fn ends_with(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("ends_with", &args, 2, 2)?;
+    let s = string_arg("ends_with", &args, 0)?;
+    let suffix = string_arg("ends_with", &args, 1)?;
+    Ok(Value::Boolean(s.ends_with(&*suffix)))
+}
+
+/// Returns the index of the first occurrence of `needle`, or -1.
+pub fn find(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("find", &args, 2, 2)?;
+    let s = string_arg("find", &args, 0)?;
+    let needle = string_arg("find", &args, 1)?;
+
+    match s.find(&*needle) {
+        Some(byte_index) => Ok(Value::Int(char_len(&s[..byte_index]) as i64)),
+        None => Ok(Value::Int(-1)),
+    }
+}
+
+/// `substring(s, start, end)`, where `end` defaults to the length of `s`.
+pub fn substring(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("substring", &args, 2, 3)?;
+    let s = string_arg("substring", &args, 0)?;
+    let start = expect_index("substring", &args[1])?;
+    let end = match args.get(2) {
+        Some(v) => expect_index("substring", v)?,
+        None => char_len(&s),
+    };
+
+    match (byte_offset(&s, start), byte_offset(&s, end)) {
+        (Some(from), Some(to)) if from <= to => Ok(Value::string(s[from..to].to_string())),
+        _ => Err(format!("substring: invalid range {}..{} of string with length of {}", start, end, char_len(&s))),
+    }
+}
+
+pub fn repeat(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("repeat", &args, 2, 2)?;
+    let s = string_arg("repeat", &args, 0)?;
+    let count = expect_index("repeat", &args[1])?;
+    limits::check(s.len().saturating_mul(count), char_len(&s).saturating_mul(count))?;
+    Ok(Value::string(s.repeat(count)))
+}
+
+fn pad(name: &str, args: Vec<Value>, left: bool) -> Result<Value, String> {
+    check_arg_count(name, &args, 2, 3)?;
+    let s = string_arg(name, &args, 0)?;
+    let width = expect_index(name, &args[1])?;
+    limits::check(width, width)?;
+    let fill = match args.get(2) {
+        Some(_) => string_arg(name, &args, 2)?,
+        None => " ".to_string(),
+    };
+
+    let mut fill_chars = fill.chars();
+    let fill_char = match (fill_chars.next(), fill_chars.next()) {
+        (Some(c), None) => c,
+        _ => return Err(format!("{}: padding must be a single character, found \"{}\"", name, fill)),
+    };
+
+    let padding = iter::repeat_n(fill_char, width.saturating_sub(char_len(&s))).collect::<String>();
+    if left {
+        Ok(Value::string(padding + &s))
+    } else {
+        Ok(Value::string(s + &padding))
+    }
+}
+
+pub fn pad_left(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    pad("pad_left", args, true)
+}
+
+pub fn pad_right(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    pad("pad_right", args, false)
+}
+
+pub fn chars(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("chars", &args, 1, 1)?;
+    let s = string_arg("chars", &args, 0)?;
+    Ok(Value::array(s.chars().map(|c| Value::string(c.to_string())).collect()))
+}
+
+/// `char_code(s, index)` returns the code point of a character, `index` defaulting to 0.
+pub fn char_code(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("char_code", &args, 1, 2)?;
+    let s = string_arg("char_code", &args, 0)?;
+    let index = match args.get(1) {
+        Some(v) => expect_index("char_code", v)?,
+        None => 0,
+    };
+
+    match s.chars().nth(index) {
+        Some(c) => Ok(Value::Int(c as i64)),
+        None => Err(format!("char_code: attempted to access index {} of string with length of {}", index, char_len(&s))),
+    }
+}
+
+pub fn from_char_code(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("from_char_code", &args, 1, usize::MAX)?;
+    let mut s = String::new();
+    for arg in &args {
+        let n = expect_num("from_char_code", arg)?;
+        let c = if n >= 0.0 && n.fract() == 0.0 && n <= u32::MAX as f64 {
+            char::from_u32(n as u32)
+        } else {
+            None
+        };
+
+        match c {
+            Some(c) => s.push(c),
+            None => return Err(format!("from_char_code: {} is not a valid character code", n)),
+        }
+    }
+    Ok(Value::string(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use env::Env;
+
+    fn call(f: Builtin, args: Vec<Value>) -> Result<Value, String> {
+        f(&mut Context::new(&mut |_, _| unreachable!(), &[], &Env::default()), args)
+    }
+
+    fn string(s: &str) -> Value {
+        Value::string(s.to_string())
+    }
+
+    #[test]
+    fn indices_count_chars() {
+        assert_eq!(call(find, vec![string("héllo"), string("l")]), Ok(Value::Int(2)));
+        assert_eq!(call(substring, vec![string("héllo"), Value::Num(1.0), Value::Num(3.0)]), Ok(string("él")));
+        assert!(call(substring, vec![string("héllo"), Value::Num(3.0), Value::Num(6.0)]).is_err());
+    }
+
+    #[test]
+    fn split_and_join() {
+        let parts = call(split, vec![string("a,b,,c"), string(",")]).unwrap();
+        assert_eq!(parts, Value::array(vec![string("a"), string("b"), string(""), string("c")]));
+        assert_eq!(call(join, vec![parts, string("-")]), Ok(string("a-b--c")));
+    }
+}
@@ -0,0 +1,495 @@
+use super::*;
+
+use std::cell::Cell;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Timestamps are seconds since the Unix epoch. Only UTC and fixed offsets
+// (written "+05:30", "-0800" or "Z") are supported, there is no time zone
+// database.
+
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+thread_local! {
+    static CLOCK_START: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Seconds since the Unix epoch
+pub fn now(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("now", &args, 0, 0)?;
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => Ok(Value::Num(d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9)),
+        Err(e) => Ok(Value::Num(-(e.duration().as_secs() as f64 + e.duration().subsec_nanos() as f64 / 1e9))),
+    }
+}
+
+/// Seconds since the first call to `clock()`. Unlike `now()`, this never goes
+/// backwards, so the difference between two calls measures elapsed time.
+pub fn clock(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("clock", &args, 0, 0)?;
+    let start = CLOCK_START.with(|start| {
+        let instant = start.get().unwrap_or_else(Instant::now);
+        start.set(Some(instant));
+        instant
+    });
+    let elapsed = start.elapsed();
+    Ok(Value::Num(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9))
+}
+
+pub fn sleep(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("sleep", &args, 1, 1)?;
+    let secs = expect_num("sleep", &args[0])?;
+    if !secs.is_finite() || secs < 0.0 {
+        return Err(format!("sleep: invalid duration: {}", secs));
+    }
+
+    limits::sleep(Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32))?;
+    Ok(Value::Void)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a date in the proleptic Gregorian calendar
+// (Howard Hinnant's `days_from_civil`).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[derive(Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    nanosecond: i64,
+    offset: i64, // seconds east of UTC
+}
+
+impl DateTime {
+    fn from_timestamp(ts: f64, offset: i64) -> Result<Self, String> {
+        // Limited to about ±285,000 years, where f64 still has sub-second precision
+        if !ts.is_finite() || ts.abs() > 9e15 {
+            return Err(format!("timestamp out of range: {}", ts));
+        }
+
+        let secs = ts.floor() as i64 + offset;
+        let nanosecond = ((ts - ts.floor()) * 1e9).round().min(999_999_999.0) as i64;
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        Ok(DateTime {
+            year: year,
+            month: month,
+            day: day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+            nanosecond: nanosecond,
+            offset: offset,
+        })
+    }
+
+    fn timestamp(&self) -> f64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = days * 86400 + self.hour * 3600 + self.minute * 60 + self.second - self.offset;
+        secs as f64 + self.nanosecond as f64 / 1e9
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.month < 1 || self.month > 12 {
+            return Err(format!("invalid month: {}", self.month));
+        }
+        if self.day < 1 || self.day > days_in_month(self.year, self.month) {
+            return Err(format!("invalid day of month: {}", self.day));
+        }
+        if self.hour > 23 || self.minute > 59 || self.second > 60 || self.hour < 0 || self.minute < 0 || self.second < 0 {
+            return Err(format!("invalid time: {:02}:{:02}:{:02}", self.hour, self.minute, self.second));
+        }
+        Ok(())
+    }
+
+    /// 0 is Sunday
+    fn weekday(&self) -> i64 {
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7)
+    }
+
+    /// 1 is January 1st
+    fn yearday(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1
+    }
+}
+
+fn format_offset(offset: i64, colon: bool) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    if colon {
+        format!("{}{:02}:{:02}", sign, offset / 3600, offset % 3600 / 60)
+    } else {
+        format!("{}{:02}{:02}", sign, offset / 3600, offset % 3600 / 60)
+    }
+}
+
+/// Parses "Z", "+05:30", "+0530" or "+05", returning seconds east of UTC.
+fn parse_offset(s: &str) -> Result<i64, String> {
+    if s == "Z" || s == "z" {
+        return Ok(0);
+    }
+
+    let invalid = || format!("invalid UTC offset: {}", s);
+    let sign = match s.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let digits = s[1..].replace(":", "");
+    if !digits.chars().all(|c| c.is_ascii_digit()) || (digits.len() != 2 && digits.len() != 4) {
+        return Err(invalid());
+    }
+
+    let hours = digits[..2].parse::<i64>().unwrap();
+    let minutes = if digits.len() == 4 { digits[2..].parse::<i64>().unwrap() } else { 0 };
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+fn strftime(dt: &DateTime, fmt: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let hour12 = if dt.hour % 12 == 0 { 12 } else { dt.hour % 12 };
+        match chars.next() {
+            Some('Y') => out.push_str(&dt.year.to_string()),
+            Some('y') => out.push_str(&format!("{:02}", dt.year.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{:02}", dt.month)),
+            Some('d') => out.push_str(&format!("{:02}", dt.day)),
+            Some('e') => out.push_str(&format!("{:2}", dt.day)),
+            Some('H') => out.push_str(&format!("{:02}", dt.hour)),
+            Some('I') => out.push_str(&format!("{:02}", hour12)),
+            Some('M') => out.push_str(&format!("{:02}", dt.minute)),
+            Some('S') => out.push_str(&format!("{:02}", dt.second)),
+            Some('f') => out.push_str(&format!("{:03}", dt.nanosecond / 1_000_000)),
+            Some('p') => out.push_str(if dt.hour < 12 { "AM" } else { "PM" }),
+            Some('j') => out.push_str(&format!("{:03}", dt.yearday())),
+            Some('a') => out.push_str(&WEEKDAYS[dt.weekday() as usize][..3]),
+            Some('A') => out.push_str(WEEKDAYS[dt.weekday() as usize]),
+            Some('w') => out.push_str(&dt.weekday().to_string()),
+            Some('u') => out.push_str(&(if dt.weekday() == 0 { 7 } else { dt.weekday() }).to_string()),
+            Some('b') => out.push_str(&MONTHS[dt.month as usize - 1][..3]),
+            Some('B') => out.push_str(MONTHS[dt.month as usize - 1]),
+            Some('z') => out.push_str(&format_offset(dt.offset, false)),
+            Some('Z') => out.push_str(&if dt.offset == 0 { "UTC".to_string() } else { format_offset(dt.offset, true) }),
+            Some('s') => out.push_str(&(dt.timestamp().floor() as i64).to_string()),
+            Some('F') => out.push_str(&format!("{}-{:02}-{:02}", dt.year, dt.month, dt.day)),
+            Some('T') => out.push_str(&format!("{:02}:{:02}:{:02}", dt.hour, dt.minute, dt.second)),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('%') => out.push('%'),
+            Some(other) => return Err(format!("unknown format directive: %{}", other)),
+            None => return Err("format string ends with '%'".to_string()),
+        }
+    }
+
+    Ok(out)
+}
+
+struct Scanner<'s> {
+    input: &'s str,
+    pos: usize,
+}
+
+impl<'s> Scanner<'s> {
+    fn rest(&self) -> &'s str {
+        &self.input[self.pos..]
+    }
+
+    // Reads up to `max` digits, with an optional sign if `signed`.
+    fn number(&mut self, max: usize, signed: bool) -> Result<i64, String> {
+        let rest = self.rest();
+        let sign_len = if signed && (rest.starts_with('-') || rest.starts_with('+')) { 1 } else { 0 };
+        let digits = rest[sign_len..].chars().take(max).take_while(|c| c.is_ascii_digit()).count();
+        if digits == 0 {
+            return Err(format!("expected number at \"{}\"", rest));
+        }
+
+        let text = &rest[..sign_len + digits];
+        self.pos += text.len();
+        text.parse::<i64>().map_err(|e| e.to_string())
+    }
+
+    fn word(&mut self, words: &[&str], abbreviated: bool) -> Result<i64, String> {
+        let rest = self.rest();
+        for (i, word) in words.iter().enumerate() {
+            let word = if abbreviated { &word[..3] } else { word };
+            if rest.len() >= word.len() && rest[..word.len()].eq_ignore_ascii_case(word) {
+                self.pos += word.len();
+                return Ok(i as i64);
+            }
+        }
+        Err(format!("expected name at \"{}\"", rest))
+    }
+
+    fn literal(&mut self, c: char) -> Result<(), String> {
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at \"{}\"", c, self.rest()))
+        }
+    }
+}
+
+fn strptime(s: &str, fmt: &str) -> Result<DateTime, String> {
+    let mut dt = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0, nanosecond: 0, offset: 0 };
+    let mut scanner = Scanner { input: s, pos: 0 };
+    let mut pm = None;
+    let mut chars = fmt.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            if c.is_whitespace() {
+                while scanner.rest().starts_with(char::is_whitespace) {
+                    scanner.pos += 1;
+                }
+            } else {
+                scanner.literal(c)?;
+            }
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => dt.year = scanner.number(4, true)?,
+            Some('y') => dt.year = 2000 + scanner.number(2, false)?,
+            Some('m') => dt.month = scanner.number(2, false)?,
+            Some('d') | Some('e') => {
+                while scanner.rest().starts_with(' ') {
+                    scanner.pos += 1;
+                }
+                dt.day = scanner.number(2, false)?
+            },
+            Some('H') => dt.hour = scanner.number(2, false)?,
+            Some('I') => dt.hour = scanner.number(2, false)? % 12,
+            Some('M') => dt.minute = scanner.number(2, false)?,
+            Some('S') => dt.second = scanner.number(2, false)?,
+            Some('f') => {
+                let start = scanner.pos;
+                let n = scanner.number(9, false)?;
+                let digits = (scanner.pos - start) as u32;
+                dt.nanosecond = n * 10i64.pow(9 - digits);
+            },
+            Some('p') => pm = Some(scanner.word(&["AM", "PM"], false)? == 1),
+            Some('b') => dt.month = scanner.word(&MONTHS, true)? + 1,
+            Some('B') => dt.month = scanner.word(&MONTHS, false)? + 1,
+            Some('a') => { scanner.word(&WEEKDAYS, true)?; },
+            Some('A') => { scanner.word(&WEEKDAYS, false)?; },
+            Some('z') => {
+                let len = scanner.rest().find(|c: char| !(c.is_ascii_digit() || "+-:Zz".contains(c))).unwrap_or(scanner.rest().len());
+                dt.offset = parse_offset(&scanner.rest()[..len])?;
+                scanner.pos += len;
+            },
+            Some('s') => {
+                let ts = scanner.number(20, true)?;
+                dt = DateTime::from_timestamp(ts as f64, 0)?;
+            },
+            Some('F') => {
+                dt.year = scanner.number(4, true)?;
+                scanner.literal('-')?;
+                dt.month = scanner.number(2, false)?;
+                scanner.literal('-')?;
+                dt.day = scanner.number(2, false)?;
+            },
+            Some('T') => {
+                dt.hour = scanner.number(2, false)?;
+                scanner.literal(':')?;
+                dt.minute = scanner.number(2, false)?;
+                scanner.literal(':')?;
+                dt.second = scanner.number(2, false)?;
+            },
+            Some('%') => scanner.literal('%')?,
+            Some(other) => return Err(format!("unknown format directive: %{}", other)),
+            None => return Err("format string ends with '%'".to_string()),
+        }
+    }
+
+    if !scanner.rest().is_empty() {
+        return Err(format!("unexpected text at \"{}\"", scanner.rest()));
+    }
+    if let Some(pm) = pm {
+        dt.hour = dt.hour % 12 + if pm { 12 } else { 0 };
+    }
+    dt.validate()?;
+    Ok(dt)
+}
+
+fn offset_arg(name: &str, args: &[Value], i: usize) -> Result<i64, String> {
+    match args.get(i) {
+        Some(v) => parse_offset(expect_string(name, v)?).map_err(|e| format!("{}: {}", name, e)),
+        None => Ok(0),
+    }
+}
+
+/// `date(timestamp, offset)` breaks a timestamp down into a dict of its parts.
+pub fn date(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("date", &args, 1, 2)?;
+    let ts = expect_num("date", &args[0])?;
+    let offset = offset_arg("date", &args, 1)?;
+    let dt = DateTime::from_timestamp(ts, offset).map_err(|e| format!("date: {}", e))?;
+
+    let mut dict = BTreeMap::new();
+    for &(key, value) in &[
+        ("year", dt.year),
+        ("month", dt.month),
+        ("day", dt.day),
+        ("hour", dt.hour),
+        ("minute", dt.minute),
+        ("second", dt.second),
+        ("nanosecond", dt.nanosecond),
+        ("weekday", dt.weekday()),
+        ("yearday", dt.yearday()),
+        ("offset", dt.offset),
+    ] {
+        dict.insert(key.to_string(), Value::Int(value));
+    }
+    Ok(Value::dict(dict))
+}
+
+/// The inverse of `date`. Missing parts default to the start of the Unix epoch.
+pub fn timestamp(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("timestamp", &args, 1, 1)?;
+    let dict = expect_dict("timestamp", args.into_iter().next().unwrap())?;
+
+    let part = |key: &str, default: i64| -> Result<i64, String> {
+        match dict.get(key) {
+            Some(v) => {
+                let n = expect_num("timestamp", v)?;
+                if n.fract() != 0.0 || n.abs() > 1e12 {
+                    return Err(format!("timestamp: invalid {}: {}", key, n));
+                }
+                Ok(n as i64)
+            },
+            None => Ok(default),
+        }
+    };
+
+    let dt = DateTime {
+        year: part("year", 1970)?,
+        month: part("month", 1)?,
+        day: part("day", 1)?,
+        hour: part("hour", 0)?,
+        minute: part("minute", 0)?,
+        second: part("second", 0)?,
+        nanosecond: part("nanosecond", 0)?,
+        offset: part("offset", 0)?,
+    };
+    dt.validate().map_err(|e| format!("timestamp: {}", e))?;
+    Ok(Value::Num(dt.timestamp()))
+}
+
+/// `format_time(timestamp, format, offset)` using strftime-style directives.
+pub fn format_time(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("format_time", &args, 2, 3)?;
+    let ts = expect_num("format_time", &args[0])?;
+    let fmt = expect_string("format_time", &args[1])?;
+    let offset = offset_arg("format_time", &args, 2)?;
+
+    DateTime::from_timestamp(ts, offset)
+        .and_then(|dt| strftime(&dt, fmt))
+        .map(Value::string)
+        .map_err(|e| format!("format_time: {}", e))
+}
+
+/// `parse_time(s, format)` returns a timestamp. Times are UTC unless the format has `%z`.
+pub fn parse_time(_: &mut Context, args: Vec<Value>) -> Result<Value, String> {
+    check_arg_count("parse_time", &args, 2, 2)?;
+    let s = expect_string("parse_time", &args[0])?;
+    let fmt = expect_string("parse_time", &args[1])?;
+
+    strptime(s, fmt)
+        .map(|dt| Value::Num(dt.timestamp()))
+        .map_err(|e| format!("parse_time: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_round_trip() {
+        for &days in &[-719468, -1, 0, 1, 11016, 19358, 2932896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn format_with_offset() {
+        // 2021-03-04 05:06:07.5 UTC, a Thursday
+        let dt = DateTime::from_timestamp(1614834367.5, 0).unwrap();
+        assert_eq!(strftime(&dt, "%F %T.%f %a %j %Z").unwrap(), "2021-03-04 05:06:07.500 Thu 063 UTC");
+
+        let dt = DateTime::from_timestamp(1614834367.0, parse_offset("-08:00").unwrap()).unwrap();
+        assert_eq!(strftime(&dt, "%d %B %Y %I:%M %p %z").unwrap(), "03 March 2021 09:06 PM -0800");
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let dt = strptime("2021-03-03 21:06:07 -0800", "%Y-%m-%d %H:%M:%S %z").unwrap();
+        assert_eq!(dt.timestamp(), 1614834367.0);
+        let dt = strptime("Mar 4 1999, 12:30 am", "%b %d %Y, %I:%M %p").unwrap();
+        assert_eq!(strftime(&dt, "%FT%T").unwrap(), "1999-03-04T00:30:00");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(strptime("2021-02-29", "%F").is_err());
+        assert!(strptime("2021-01-01 extra", "%F").is_err());
+        assert!(strptime("12:61:00", "%T").is_err());
+        assert!(parse_offset("+5").is_err());
+    }
+}
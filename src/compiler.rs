@@ -0,0 +1,380 @@
+use ast::*;
+use builtins::{self, Builtin};
+
+// Turns resolved statements into bytecode for the VM in `vm.rs`. Anything the
+// tree walking interpreter would only report when it got there, like named
+// arguments passed to a builtin, becomes a `Fail` instruction in the same
+// place, so both engines fail at the same point with the same message.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    Step, // Counts against the step limit: one at every statement and every check of a loop condition
+    Const(usize),
+    Load(Slot, usize), // (slot, var): the variable `vars[var]`, which is read straight from its slot
+    Fail(usize), // Stops with `errors[i]`
+    MakeFunc(usize), // A function from the definition in `consts[i]`
+    Cast,
+    TypeOf,
+    Array(usize), // Makes an array of the top n values
+    Dict(usize), // Makes a dict with the keys in `name_lists[i]`
+    Index,
+    BinOp(Op),
+    Compare(CompOp),
+    BoolChain(BoolLogic),
+    Unary(UnaryOp),
+    Length,
+    ToUpper,
+    ToLower,
+    JumpIfDefined(usize, usize), // (var, target): a variable hides a builtin with the same name
+    Hoisted(usize, usize), // (hoisted, target): pushes its value and jumps if it's been worked out
+    Hoist(usize), // Keeps the value on top as the hoisted expression's, unless it made anything
+    Forget(usize, usize), // (start, end): a loop is starting, so its hoisted expressions are forgotten
+    CallBuiltin(usize, usize), // (builtin, argument count)
+    Call(usize, usize), // (positional argument count, names of the named ones)
+    Pop,
+    Declare(usize), // Binds `patterns[i]`
+    DeclareConst(usize, usize), // (pattern, line)
+    CheckAssign(usize), // The vars in `var_lists[i]` must be declared and not constants
+    Assign(AssignOp, usize, usize), // (op, vars, value count)
+    Jump(usize),
+    JumpIfFalse(usize),
+    JumpIfFalseElif(usize),
+    Print(&'static str, usize, bool), // (statement, value count, whether the first value is a format string)
+    Exit,
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instr>,
+    pub consts: Vec<Value>,
+    pub vars: Vec<Var>,
+    pub var_lists: Vec<Vec<Var>>,
+    pub name_lists: Vec<Vec<String>>,
+    pub patterns: Vec<Pattern>,
+    pub builtins: Vec<(Builtin, Vec<Option<String>>)>, // (builtin, argument names for callback errors)
+    pub errors: Vec<String>,
+}
+
+struct Compiler {
+    chunk: Chunk,
+    // Jumps to patch to the end of the current statement. The tree walker
+    // ignores what a `return` returns at the top level and inside `while`, so
+    // there a `return` just skips to the next statement. `None` means a
+    // `return` really returns from the function.
+    returns: Option<Vec<usize>>,
+}
+
+/// The whole script, or a line typed into the REPL.
+pub fn compile_program(statements: &[Statement]) -> Chunk {
+    let mut compiler = Compiler::new();
+    compiler.statements_ignoring_returns(statements);
+    compiler.finish()
+}
+
+pub fn compile_function(body: &[Statement]) -> Chunk {
+    let mut compiler = Compiler::new();
+    for s in body {
+        compiler.statement(s);
+    }
+    compiler.finish()
+}
+
+/// Code that leaves the value of `expr` as the result.
+pub fn compile_expr(expr: &Expr) -> Chunk {
+    let mut compiler = Compiler::new();
+    compiler.expr(expr);
+    compiler.emit(Instr::Return);
+    compiler.chunk
+}
+
+impl Compiler {
+    fn new() -> Compiler {
+        Compiler {
+            chunk: Chunk::default(),
+            returns: None,
+        }
+    }
+
+    fn finish(mut self) -> Chunk {
+        let void = self.constant(Value::Void);
+        self.emit(Instr::Const(void));
+        self.emit(Instr::Return);
+        self.chunk
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.chunk.code.push(instr);
+        self.chunk.code.len() - 1
+    }
+
+    // Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.chunk.code.len();
+        match self.chunk.code[at] {
+            Instr::Jump(ref mut t)
+            | Instr::JumpIfFalse(ref mut t)
+            | Instr::JumpIfFalseElif(ref mut t)
+            | Instr::JumpIfDefined(_, ref mut t)
+            | Instr::Hoisted(_, ref mut t) => *t = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        self.chunk.consts.push(value);
+        self.chunk.consts.len() - 1
+    }
+
+    fn var(&mut self, var: &Var) -> usize {
+        match self.chunk.vars.iter().position(|v| v.name == var.name && v.slot == var.slot) {
+            Some(i) => i,
+            None => {
+                self.chunk.vars.push(var.clone());
+                self.chunk.vars.len() - 1
+            },
+        }
+    }
+
+    fn name_list(&mut self, names: Vec<String>) -> usize {
+        self.chunk.name_lists.push(names);
+        self.chunk.name_lists.len() - 1
+    }
+
+    fn fail(&mut self, error: String) {
+        self.chunk.errors.push(error);
+        let i = self.chunk.errors.len() - 1;
+        self.emit(Instr::Fail(i));
+    }
+
+    fn statements_ignoring_returns(&mut self, statements: &[Statement]) {
+        let outer = self.returns.take();
+        for s in statements {
+            self.returns = Some(Vec::new());
+            self.statement(s);
+            for at in self.returns.take().unwrap() {
+                self.patch(at);
+            }
+        }
+        self.returns = outer;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.emit(Instr::Step);
+        match *statement {
+            Statement::DeclareVar(ref pattern, ref expr) => {
+                self.expr(expr);
+                self.chunk.patterns.push(pattern.clone());
+                let i = self.chunk.patterns.len() - 1;
+                self.emit(Instr::Declare(i));
+            },
+            Statement::DeclareConst(ref pattern, ref expr, line) => {
+                self.expr(expr);
+                self.chunk.patterns.push(pattern.clone());
+                let i = self.chunk.patterns.len() - 1;
+                self.emit(Instr::DeclareConst(i, line));
+            },
+            Statement::MutateVar(op, ref vars, ref exprs) => {
+                self.chunk.var_lists.push(vars.clone());
+                let list = self.chunk.var_lists.len() - 1;
+                // Nothing happens between the check and the assignment for a literal like
+                // the one in `i += 1;`, and the assignment makes the same checks
+                match (&vars[..], &exprs[..]) {
+                    (&[_], &[Expr::Literal(_)]) => {},
+                    _ => { self.emit(Instr::CheckAssign(list)); },
+                }
+                for expr in exprs {
+                    self.expr(expr);
+                }
+                self.emit(Instr::Assign(op, list, exprs.len()));
+            },
+            Statement::Expression(ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::Pop);
+            },
+            Statement::Return(ref expr) => {
+                self.expr(expr);
+                if self.returns.is_some() {
+                    self.emit(Instr::Pop);
+                    let jump = self.emit(Instr::Jump(0));
+                    self.returns.as_mut().unwrap().push(jump);
+                } else {
+                    self.emit(Instr::Return);
+                }
+            },
+            Statement::If(ref if_s, ref elif_s, ref else_s) => {
+                let mut ends = Vec::new();
+
+                self.expr(&if_s.e);
+                let mut next = self.emit(Instr::JumpIfFalse(0));
+                for s in &if_s.s {
+                    self.statement(s);
+                }
+                ends.push(self.emit(Instr::Jump(0)));
+
+                for elif in elif_s.iter().flatten() {
+                    self.patch(next);
+                    self.expr(&elif.e);
+                    next = self.emit(Instr::JumpIfFalseElif(0));
+                    for s in &elif.s {
+                        self.statement(s);
+                    }
+                    ends.push(self.emit(Instr::Jump(0)));
+                }
+
+                self.patch(next);
+                for s in else_s.iter().flatten() {
+                    self.statement(s);
+                }
+                for at in ends {
+                    self.patch(at);
+                }
+            },
+            Statement::While(ref condition, ref statements, ref hoisted) => {
+                if !hoisted.is_empty() {
+                    self.emit(Instr::Forget(hoisted.start, hoisted.end));
+                }
+                let start = self.emit(Instr::Step);
+                self.expr(condition);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.statements_ignoring_returns(statements);
+                self.emit(Instr::Jump(start));
+                self.patch(exit);
+            },
+            Statement::Print(ref exprs) => self.print("print", exprs),
+            Statement::Println(ref exprs) => self.print("println", exprs),
+            Statement::EPrint(ref exprs) => self.print("eprint", exprs),
+            Statement::EPrintln(ref exprs) => self.print("eprintln", exprs),
+            Statement::Exit(ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::Exit);
+            },
+        }
+    }
+
+    fn print(&mut self, statement: &'static str, exprs: &[Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+        self.emit(Instr::Print(statement, exprs.len(), ::is_format(exprs)));
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Literal(ref v) => {
+                let i = self.constant(v.clone());
+                self.emit(Instr::Const(i));
+            },
+            Expr::FuncDef(ref v) => {
+                let i = self.constant(v.clone());
+                self.emit(Instr::MakeFunc(i));
+            },
+            Expr::Reference(ref var) => match var.slot {
+                Some(slot) => {
+                    let i = self.var(var);
+                    self.emit(Instr::Load(slot, i));
+                },
+                None => self.fail(format!("Undefined variable: {}", var.name)),
+            },
+            Expr::Typecast(ref expression, ref new_type) => {
+                self.expr(expression);
+                self.expr(new_type);
+                self.emit(Instr::Cast);
+            },
+            Expr::TypeOf(ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::TypeOf);
+            },
+            Expr::CallFunc(ref f_ident, ref args, ref named) => {
+                let mut builtin_end = None;
+                if let Expr::Reference(ref var) = **f_ident {
+                    if let Some(builtin) = builtins::lookup(&var.name) {
+                        let i = self.var(var);
+                        let defined = self.emit(Instr::JumpIfDefined(i, 0));
+                        if let Some(&(ref arg, _)) = named.first() {
+                            self.fail(format!("{} doesn't take named arguments (found {})", var.name, arg));
+                        } else {
+                            for arg in args {
+                                self.expr(arg);
+                            }
+                            self.chunk.builtins.push((builtin, ::arg_names(args)));
+                            let b = self.chunk.builtins.len() - 1;
+                            self.emit(Instr::CallBuiltin(b, args.len()));
+                        }
+                        builtin_end = Some(self.emit(Instr::Jump(0)));
+                        self.patch(defined);
+                    }
+                }
+
+                self.expr(f_ident);
+                for arg in args {
+                    self.expr(arg);
+                }
+                for &(_, ref expr) in named {
+                    self.expr(expr);
+                }
+                let names = self.name_list(named.iter().map(|&(ref name, _)| name.clone()).collect());
+                self.emit(Instr::Call(args.len(), names));
+
+                if let Some(at) = builtin_end {
+                    self.patch(at);
+                }
+            },
+            Expr::Array(ref exprs) => {
+                for e in exprs {
+                    self.expr(e);
+                }
+                self.emit(Instr::Array(exprs.len()));
+            },
+            Expr::Dict(ref entries) => {
+                for &(_, ref e) in entries {
+                    self.expr(e);
+                }
+                let keys = self.name_list(entries.iter().map(|&(ref key, _)| key.clone()).collect());
+                self.emit(Instr::Dict(keys));
+            },
+            Expr::Index(ref expression, ref index) => {
+                self.expr(expression);
+                self.expr(index);
+                self.emit(Instr::Index);
+            },
+            Expr::BinOp(op, ref left, ref right) => {
+                self.expr(left);
+                self.expr(right);
+                self.emit(Instr::BinOp(op));
+            },
+            Expr::Comparison(op, ref left, ref right) => {
+                self.expr(left);
+                self.expr(right);
+                self.emit(Instr::Compare(op));
+            },
+            Expr::BoolChain(op, ref left, ref right) => {
+                self.expr(left);
+                self.expr(right);
+                self.emit(Instr::BoolChain(op));
+            },
+            Expr::UnOp(op, ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::Unary(op));
+            },
+            Expr::Length(ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::Length);
+            },
+            Expr::ToUpper(ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::ToUpper);
+            },
+            Expr::ToLower(ref expr) => {
+                self.expr(expr);
+                self.emit(Instr::ToLower);
+            },
+            Expr::Hoisted(i, ref expr) => {
+                let done = self.emit(Instr::Hoisted(i, 0));
+                self.expr(expr);
+                self.emit(Instr::Hoist(i));
+                self.patch(done);
+            },
+        }
+    }
+}
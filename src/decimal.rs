@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+
+use bigint::BigInt;
+
+// Exact base 10 numbers for things like money, where 0.1 + 0.2 must be 0.3.
+// The value is `mantissa / 10^scale`. The scale is kept as written, so 1.10d
+// displays as 1.10, and sums and products keep as many places as they need.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decimal {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+/// How many decimal places a quotient is worked out to before rounding.
+const DIVISION_PLACES: u32 = 28;
+
+impl Decimal {
+    pub fn from_bigint(n: BigInt) -> Decimal {
+        Decimal {
+            mantissa: n,
+            scale: 0,
+        }
+    }
+
+    /// Parses strings like `12`, `-0.50` and `+3.`; exponents aren't allowed.
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let (int_part, frac_part) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        let digits = int_part.trim_start_matches(['-', '+']);
+        if digits.is_empty() && frac_part.is_empty() {
+            return None;
+        }
+        if !frac_part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let int_part = if digits.is_empty() { format!("{}0", int_part) } else { int_part.to_string() };
+        let mantissa = BigInt::parse(&format!("{}{}", int_part, frac_part))?;
+        Some(Decimal {
+            mantissa: mantissa,
+            scale: frac_part.len() as u32,
+        })
+    }
+
+    /// The exact value of a float, rounded to the shortest decimal that
+    /// converts back to it (so 0.1 becomes 0.1, not 0.1000000000000000055...).
+    pub fn from_f64(n: f64) -> Option<Decimal> {
+        if n.is_finite() {
+            Decimal::parse(&n.to_string())
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    /// Drops the fractional part, rounding towards zero.
+    pub fn trunc(&self) -> BigInt {
+        self.mantissa.divrem(&BigInt::pow10(self.scale)).unwrap().0
+    }
+
+    /// How many bits the digits take, ignoring the decimal point.
+    pub fn bits(&self) -> u64 {
+        self.mantissa.bits()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa.is_negative()
+    }
+
+    // The mantissa scaled up to `scale` places.
+    fn rescale(&self, scale: u32) -> Result<BigInt, String> {
+        self.mantissa.mul(&BigInt::pow10(scale - self.scale))
+    }
+
+    fn align(&self, other: &Decimal) -> Result<(BigInt, BigInt, u32), String> {
+        let scale = self.scale.max(other.scale);
+        Ok((self.rescale(scale)?, other.rescale(scale)?, scale))
+    }
+
+    pub fn add(&self, other: &Decimal) -> Result<Decimal, String> {
+        let (a, b, scale) = self.align(other)?;
+        Ok(Decimal { mantissa: a.add(&b), scale: scale })
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Result<Decimal, String> {
+        let (a, b, scale) = self.align(other)?;
+        Ok(Decimal { mantissa: a.sub(&b), scale: scale })
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Result<Decimal, String> {
+        Ok(Decimal {
+            mantissa: self.mantissa.mul(&other.mantissa)?,
+            scale: self.scale + other.scale,
+        })
+    }
+
+    /// Quotients that don't terminate are rounded (half to even) to 28 places.
+    /// Trailing zeros are dropped, but never below the scale of either operand.
+    pub fn div(&self, other: &Decimal) -> Result<Decimal, String> {
+        if other.mantissa.is_zero() {
+            return Err("decimal division by zero".to_string());
+        }
+
+        let places = DIVISION_PLACES.max(self.scale).max(other.scale);
+        let numerator = self.mantissa.mul(&BigInt::pow10(places + other.scale - self.scale))?;
+        let q = divide_rounded(&numerator, &other.mantissa)?;
+
+        let mut result = Decimal { mantissa: q, scale: places };
+        let min_scale = self.scale.max(other.scale);
+        let ten = BigInt::from_i64(10);
+        while result.scale > min_scale {
+            let (q, r) = result.mantissa.divrem(&ten).unwrap();
+            if !r.is_zero() {
+                break;
+            }
+            result = Decimal { mantissa: q, scale: result.scale - 1 };
+        }
+        Ok(result)
+    }
+
+    /// The quotient rounded towards negative infinity, as a whole number.
+    pub fn div_floor(&self, other: &Decimal) -> Result<Decimal, String> {
+        let (a, b, _) = self.align(other)?;
+        match a.div_floor(&b) {
+            Some(q) => Ok(Decimal::from_bigint(q)),
+            None => Err("decimal division by zero".to_string()),
+        }
+    }
+
+    pub fn neg(&self) -> Decimal {
+        Decimal { mantissa: self.mantissa.neg(), scale: self.scale }
+    }
+
+    /// Rounds (half to even) or pads with zeros to exactly `places` places.
+    pub fn round(&self, places: u32) -> Result<Decimal, String> {
+        let mantissa = if places >= self.scale {
+            self.rescale(places)?
+        } else {
+            divide_rounded(&self.mantissa, &BigInt::pow10(self.scale - places))?
+        };
+        Ok(Decimal { mantissa: mantissa, scale: places })
+    }
+
+    /// The remainder of truncating division, with the sign of `self`.
+    pub fn rem(&self, other: &Decimal) -> Result<Decimal, String> {
+        let (a, b, scale) = self.align(other)?;
+        match a.divrem(&b) {
+            Some((_, r)) => Ok(Decimal { mantissa: r, scale: scale }),
+            None => Err("decimal modulo by zero".to_string()),
+        }
+    }
+
+    pub fn pow(&self, exp: i64) -> Result<Decimal, String> {
+        let positive = Decimal {
+            mantissa: self.mantissa.pow(exp.unsigned_abs())?,
+            scale: (self.scale as u64).checked_mul(exp.unsigned_abs())
+                .filter(|&s| s <= u32::MAX as u64)
+                .ok_or("decimal too large".to_string())? as u32,
+        };
+        if exp < 0 {
+            Decimal::from_bigint(BigInt::from_i64(1)).div(&positive)
+        } else {
+            Ok(positive)
+        }
+    }
+}
+
+// `a / b` rounded half to even.
+fn divide_rounded(a: &BigInt, b: &BigInt) -> Result<BigInt, String> {
+    let (q, r) = a.divrem(b).unwrap();
+    let twice_rem = r.abs().mul(&BigInt::from_i64(2))?;
+    let round_away = match twice_rem.cmp(&b.abs()) {
+        Ordering::Greater => true,
+        Ordering::Equal => !q.divrem(&BigInt::from_i64(2)).unwrap().1.is_zero(),
+        Ordering::Less => false,
+    };
+
+    if round_away {
+        Ok(q.add(&BigInt::from_i64(if a.is_negative() != b.is_negative() { -1 } else { 1 })))
+    } else {
+        Ok(q)
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        self.align(other).ok().map(|(a, b, _)| a.cmp(&b))
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let digits = self.mantissa.abs().to_string();
+        let scale = self.scale as usize;
+        let digits = if digits.len() <= scale {
+            format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+        } else {
+            digits
+        };
+
+        let sign = if self.mantissa.is_negative() { "-" } else { "" };
+        let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+        if scale == 0 {
+            write!(f, "{}{}", sign, int_part)
+        } else {
+            write!(f, "{}{}.{}", sign, int_part, frac_part)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::parse(s).unwrap()
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(dec("0.1").add(&dec("0.2")).unwrap().to_string(), "0.3");
+        assert_eq!(dec("1.10").add(&dec("2")).unwrap().to_string(), "3.10");
+        assert_eq!(dec("19.99").mul(&dec("3")).unwrap().to_string(), "59.97");
+        assert_eq!(dec("-0.05").sub(&dec("0.05")).unwrap().to_string(), "-0.10");
+        assert_eq!(dec("7.5").rem(&dec("2")).unwrap().to_string(), "1.5");
+        assert_eq!(dec("-7.5").div_floor(&dec("2")).unwrap().to_string(), "-4");
+        assert_eq!(dec("1.1").pow(2).unwrap().to_string(), "1.21");
+        assert_eq!(dec("2").pow(-2).unwrap().to_string(), "0.25");
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(dec("1").div(&dec("4")).unwrap().to_string(), "0.25");
+        assert_eq!(dec("10.00").div(&dec("4")).unwrap().to_string(), "2.50");
+        assert_eq!(dec("2").div(&dec("3")).unwrap().to_string(), "0.6666666666666666666666666667");
+        assert_eq!(dec("-1").div(&dec("3")).unwrap().to_string(), "-0.3333333333333333333333333333");
+        assert!(dec("1").div(&dec("0.00")).is_err());
+        assert_eq!(dec("2.675").round(2).unwrap().to_string(), "2.68");
+        assert_eq!(dec("-0.125").round(2).unwrap().to_string(), "-0.12");
+        assert_eq!(dec("1.5").round(3).unwrap().to_string(), "1.500");
+    }
+
+    #[test]
+    fn parsing_and_conversion() {
+        assert_eq!(dec("-.5").to_string(), "-0.5");
+        assert_eq!(dec("3.").to_string(), "3");
+        assert!(Decimal::parse("1e5").is_none());
+        assert!(Decimal::parse("-").is_none());
+        assert_eq!(Decimal::from_f64(0.1).unwrap().to_string(), "0.1");
+        assert_eq!(dec("-2.75").trunc().to_string(), "-2");
+        assert!(dec("1.50") == dec("1.50"));
+        assert_eq!(dec("1.5").partial_cmp(&dec("1.50")), Some(Ordering::Equal));
+    }
+}
@@ -0,0 +1,447 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use ast::{Function, Slot, Value, Var};
+
+// The variables a statement can see. The resolver has already worked out
+// which slot each name refers to: a global, or a local of the function that's
+// running. Names are only looked up at run time for format strings like
+// "{x}", and for locals a call hasn't used yet.
+//
+// A function sees the variables it was defined with, as they were then, and
+// after those the ones its caller can see. Assigning to one of them only
+// changes the call's own copy. A call's locals start out empty and are looked
+// up the first time they're used, in the function's closure and then its
+// callers; the callers are paused until the call returns, so what it finds
+// can't change underneath it.
+//
+// Constants are stored like any other variable, along with where they were
+// declared so errors can point there.
+//
+// Every env also knows how many calls deep it is, so runaway recursion is an
+// error rather than the end of the interpreter.
+//
+// Each call, and the top level, also keeps the values of the loop-invariant
+// expressions the optimizer hoisted in its code, so a recursive call running
+// the same loop doesn't use the values its caller worked out.
+
+/// How deep calls can go unless `--max-depth` says otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 10000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Line(usize),
+    Interpreter, // Like ARGV, which is set before the script starts
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Binding {
+    value: Value,
+    origin: Option<Origin>, // Where it was declared, if it's a constant
+}
+
+struct Globals {
+    names: HashMap<String, usize>,
+    slots: Vec<Option<Binding>>, // `None` for a name that's declared somewhere but hasn't been set
+    hoisted: Vec<Option<Value>>, // For the code at the top level
+    max_depth: usize,
+}
+
+impl Default for Globals {
+    fn default() -> Globals {
+        Globals {
+            names: HashMap::new(),
+            slots: Vec::new(),
+            hoisted: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// What each local of a function was where the function was defined.
+#[derive(Debug, PartialEq)]
+pub struct Closure(Vec<Option<Binding>>);
+
+#[derive(Clone)]
+enum Local {
+    Unknown, // Not used yet, so it hasn't been looked up
+    Undefined, // Not a variable anywhere the call can see
+    Bound(Binding),
+}
+
+// The locals of one call. Nothing else holds on to it once the call and the
+// calls it makes have returned.
+struct Scope {
+    func: Rc<Function>,
+    closure: Option<Rc<Closure>>,
+    locals: RefCell<Vec<Local>>,
+    hoisted: RefCell<Vec<Option<Value>>>,
+    caller: Option<Rc<Scope>>, // `None` when it was called from the top level
+}
+
+#[derive(Clone, Default)]
+pub struct Env {
+    globals: Rc<RefCell<Globals>>,
+    scope: Option<Rc<Scope>>, // `None` at the top level
+    depth: usize, // How many calls deep this is
+}
+
+impl Env {
+    /// The global slot for `name`, if it has one.
+    pub fn global_slot(&self, name: &str) -> Option<usize> {
+        self.globals.borrow().names.get(name).cloned()
+    }
+
+    /// Gives `name` a global slot, if it doesn't already have one.
+    pub fn add_global(&mut self, name: &str) -> usize {
+        let mut globals = self.globals.borrow_mut();
+        if let Some(&slot) = globals.names.get(name) {
+            return slot;
+        }
+        let slot = globals.slots.len();
+        globals.names.insert(name.to_string(), slot);
+        globals.slots.push(None);
+        slot
+    }
+
+    /// Sets a global before the script starts, like a builtin constant.
+    pub fn define(&mut self, name: &str, value: Value, origin: Option<Origin>) {
+        let slot = self.add_global(name);
+        self.globals.borrow_mut().slots[slot] = Some(Binding { value: value, origin: origin });
+    }
+
+    /// Limits how deep calls can go.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.globals.borrow_mut().max_depth = max_depth;
+    }
+
+    /// The value of hoisted expression `i`, if it's been worked out since its loop started.
+    pub fn hoisted(&self, i: usize) -> Option<Value> {
+        self.with_hoisted(|hoisted| hoisted.get(i).and_then(|value| value.clone()))
+    }
+
+    /// Keeps the value of hoisted expression `i` until its loop starts again.
+    pub fn hoist(&self, i: usize, value: Value) {
+        self.with_hoisted(|hoisted| {
+            if hoisted.len() <= i {
+                hoisted.resize(i + 1, None);
+            }
+            hoisted[i] = Some(value);
+        })
+    }
+
+    /// Forgets the hoisted expressions of a loop that's starting.
+    pub fn forget_hoisted(&self, range: Range<usize>) {
+        self.with_hoisted(|hoisted| {
+            for value in hoisted.iter_mut().take(range.end).skip(range.start) {
+                *value = None;
+            }
+        })
+    }
+
+    fn with_hoisted<T, F: FnOnce(&mut Vec<Option<Value>>) -> T>(&self, f: F) -> T {
+        match self.scope {
+            Some(ref scope) => f(&mut scope.hoisted.borrow_mut()),
+            None => f(&mut self.globals.borrow_mut().hoisted),
+        }
+    }
+
+    /// The closure for `func` when it's defined here.
+    pub fn closure(&self, func: &Function) -> Rc<Closure> {
+        Rc::new(Closure(func.captures.iter().map(|&slot| self.binding(slot)).collect()))
+    }
+
+    /// The env a call to `func` starts with, where `closure` is what it was defined with.
+    pub fn call(&self, closure: Option<Rc<Closure>>, func: Rc<Function>) -> Result<Env, String> {
+        let max_depth = self.globals.borrow().max_depth;
+        if self.depth >= max_depth {
+            let name = func.name.as_ref().map_or("an anonymous function", |name| name);
+            return Err(format!("maximum call depth of {} exceeded calling {}", max_depth, name));
+        }
+
+        let locals = vec![Local::Unknown; func.locals.len()];
+        Ok(Env {
+            globals: self.globals.clone(),
+            scope: Some(Rc::new(Scope {
+                func: func,
+                closure: closure,
+                locals: RefCell::new(locals),
+                hoisted: RefCell::new(Vec::new()),
+                caller: self.scope.clone(),
+            })),
+            depth: self.depth + 1,
+        })
+    }
+
+    // Looks `name` up the way a function called from `scope` would, filling
+    // in the locals of the calls on the way that haven't used it yet.
+    fn find(&self, start: Option<&Rc<Scope>>, name: &str) -> Option<Binding> {
+        let mut scope = start;
+        let found = loop {
+            let s = match scope {
+                Some(s) => s,
+                None => {
+                    let globals = self.globals.borrow();
+                    break globals.names.get(name).and_then(|&i| globals.slots[i].clone());
+                },
+            };
+            if let Some(i) = s.func.locals.iter().position(|local| local == name) {
+                match s.locals.borrow()[i] {
+                    Local::Bound(ref binding) => break Some(binding.clone()),
+                    Local::Undefined => break None,
+                    Local::Unknown => {},
+                }
+                if let Some(binding) = s.closure.as_ref().and_then(|c| c.0[i].clone()) {
+                    break Some(binding);
+                }
+            }
+            scope = s.caller.as_ref();
+        };
+
+        // Going over the same calls again is quicker than keeping a list of them
+        let mut scope = start;
+        while let Some(s) = scope {
+            if let Some(i) = s.func.locals.iter().position(|local| local == name) {
+                let mut locals = s.locals.borrow_mut();
+                match locals[i] {
+                    Local::Unknown => {},
+                    _ => break,
+                }
+                locals[i] = match found {
+                    Some(ref binding) => Local::Bound(binding.clone()),
+                    None => Local::Undefined,
+                };
+                if s.closure.as_ref().map_or(false, |c| c.0[i].is_some()) {
+                    break;
+                }
+            }
+            scope = s.caller.as_ref();
+        }
+        found
+    }
+
+    // Makes sure local `i` has been looked up.
+    fn resolve(&self, i: usize) {
+        let scope = self.scope.as_ref().unwrap();
+        let unknown = match scope.locals.borrow()[i] {
+            Local::Unknown => true,
+            _ => false,
+        };
+        if unknown {
+            self.find(Some(scope), &scope.func.locals[i]);
+        }
+    }
+
+    fn binding(&self, slot: Slot) -> Option<Binding> {
+        self.with_slot(slot, |b| b.cloned())
+    }
+
+    fn with_slot<T, F: FnOnce(Option<&Binding>) -> T>(&self, slot: Slot, f: F) -> T {
+        match slot {
+            Slot::Global(i) => f(self.globals.borrow().slots[i].as_ref()),
+            Slot::Local(i) => {
+                self.resolve(i);
+                let locals = self.scope.as_ref().unwrap().locals.borrow();
+                match locals[i] {
+                    Local::Bound(ref binding) => f(Some(binding)),
+                    _ => f(None),
+                }
+            },
+        }
+    }
+
+    // Sets a slot, once `check` has passed for what's in it now.
+    fn set_slot<F: FnOnce(Option<&Binding>) -> Result<(), String>>(&mut self, slot: Slot, binding: Binding, check: F) -> Result<(), String> {
+        self.with_slot(slot, check)?;
+        match slot {
+            Slot::Global(i) => self.globals.borrow_mut().slots[i] = Some(binding),
+            Slot::Local(i) => self.scope.as_ref().unwrap().locals.borrow_mut()[i] = Local::Bound(binding),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, var: &Var) -> Option<Value> {
+        var.slot.and_then(|slot| self.get_slot(slot))
+    }
+
+    /// The value in `slot`, for code that knows the slot but not the variable.
+    pub fn get_slot(&self, slot: Slot) -> Option<Value> {
+        match slot {
+            Slot::Global(i) => self.globals.borrow().slots[i].as_ref().map(|b| b.value.clone()),
+            Slot::Local(i) => {
+                // Most locals have been used before, so they're read without looking anything up
+                if let Local::Bound(ref binding) = self.scope.as_ref().unwrap().locals.borrow()[i] {
+                    return Some(binding.value.clone());
+                }
+                self.binding(Slot::Local(i)).map(|b| b.value)
+            },
+        }
+    }
+
+    /// Whether `var` is a variable that has been declared, rather than a builtin.
+    pub fn is_defined(&self, var: &Var) -> bool {
+        var.slot.map_or(false, |slot| self.with_slot(slot, |b| b.is_some()))
+    }
+
+    /// Looks a variable up by name, as the resolver would have.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        self.find(self.scope.as_ref(), name).map(|b| b.value)
+    }
+
+    fn check_not_const(binding: Option<&Binding>, name: &str, action: &str) -> Result<(), String> {
+        match binding.and_then(|b| b.origin.as_ref()) {
+            Some(&Origin::Line(line)) => Err(format!("cannot {} constant {} (declared on line {})", action, name, line)),
+            Some(&Origin::Interpreter) => Err(format!("cannot {} constant {} (set by the interpreter)", action, name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that `var` could be declared, without declaring anything.
+    pub fn check_declare(&self, var: &Var) -> Result<(), String> {
+        self.with_slot(var.slot.unwrap(), |b| Env::check_not_const(b, &var.name, "redeclare"))
+    }
+
+    /// `let var = value;`, or `const var = value;` when there's an origin.
+    pub fn declare(&mut self, var: &Var, value: Value, origin: Option<Origin>) -> Result<(), String> {
+        let binding = Binding { value: value, origin: origin };
+        self.set_slot(var.slot.unwrap(), binding, |b| Env::check_not_const(b, &var.name, "redeclare"))
+    }
+
+    /// `var = value;`, where `var` must already be declared.
+    pub fn assign(&mut self, var: &Var, value: Value) -> Result<(), String> {
+        let binding = Binding { value: value, origin: None };
+        self.set_slot(var.slot.unwrap(), binding, |b| Env::check_assignable(b, &var.name))
+    }
+
+    /// `var = f(var);`, where `var` must already be declared. The value is
+    /// changed where it is rather than read out and put back, so `f` mustn't
+    /// use any variables.
+    pub fn update<F: FnOnce(&Value) -> Result<Value, String>>(&mut self, var: &Var, f: F) -> Result<(), String> {
+        let (mut globals, mut locals);
+        let binding = match var.slot.unwrap() {
+            Slot::Global(i) => {
+                globals = self.globals.borrow_mut();
+                globals.slots[i].as_mut()
+            },
+            Slot::Local(i) => {
+                self.resolve(i);
+                locals = self.scope.as_ref().unwrap().locals.borrow_mut();
+                match locals[i] {
+                    Local::Bound(ref mut binding) => Some(binding),
+                    _ => None,
+                }
+            },
+        };
+        Env::check_assignable(binding.as_deref(), &var.name)?;
+
+        let binding = binding.unwrap();
+        binding.value = f(&binding.value)?;
+        Ok(())
+    }
+
+    fn check_assignable(binding: Option<&Binding>, name: &str) -> Result<(), String> {
+        match binding {
+            Some(binding) => Env::check_not_const(Some(binding), name, "assign to"),
+            None => Err(format!("undeclared variable: {}", name)),
+        }
+    }
+
+    /// Checks that `var` could be assigned to, without assigning anything.
+    pub fn check_assign(&self, var: &Var) -> Result<(), String> {
+        self.with_slot(var.slot.unwrap(), |b| Env::check_assignable(b, &var.name))
+    }
+
+    /// Binds the parameter at `index`, which hides any variable or constant with the same name.
+    pub fn bind_param(&mut self, index: usize, value: Value) {
+        self.scope.as_ref().unwrap().locals.borrow_mut()[index] = Local::Bound(Binding { value: value, origin: None });
+    }
+}
+
+impl<'a> From<Vec<(&'a str, Value)>> for Env {
+    fn from(vars: Vec<(&'a str, Value)>) -> Env {
+        let mut env = Env::default();
+        for (name, value) in vars {
+            env.define(name, value, None);
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global(env: &mut Env, name: &str) -> Var {
+        Var { name: name.to_string(), slot: Some(Slot::Global(env.add_global(name))) }
+    }
+
+    #[test]
+    fn constants_cannot_change() {
+        let mut env = Env::default();
+        let x = global(&mut env, "x");
+        let limit = global(&mut env, "LIMIT");
+        env.declare(&x, Value::Int(1), None).unwrap();
+        env.declare(&limit, Value::Int(10), Some(Origin::Line(3))).unwrap();
+
+        assert!(env.assign(&x, Value::Int(2)).is_ok());
+        assert_eq!(
+            env.assign(&limit, Value::Int(2)),
+            Err("cannot assign to constant LIMIT (declared on line 3)".to_string())
+        );
+        assert_eq!(
+            env.declare(&limit, Value::Int(2), None),
+            Err("cannot redeclare constant LIMIT (declared on line 3)".to_string())
+        );
+        assert_eq!(env.get(&limit), Some(Value::Int(10)));
+        assert_eq!(env.lookup("LIMIT"), Some(Value::Int(10)));
+    }
+
+    #[test]
+    fn calls_have_their_own_copies() {
+        let mut env = Env::default();
+        let x = global(&mut env, "x");
+        let y = global(&mut env, "y");
+        env.declare(&x, Value::Int(1), None).unwrap();
+
+        let mut func = Function::new(Vec::new(), Vec::new());
+        func.locals = vec!["x".to_string(), "y".to_string()];
+        func.captures = vec![x.slot.unwrap(), y.slot.unwrap()];
+        let closure = env.closure(&func);
+        env.assign(&x, Value::Int(2)).unwrap();
+        env.declare(&y, Value::Int(3), Some(Origin::Line(1))).unwrap();
+
+        // `x` is what it was when the function was defined, and `y` the caller's
+        let mut call = env.call(Some(closure), Rc::new(func)).unwrap();
+        let local_x = Var { name: "x".to_string(), slot: Some(Slot::Local(0)) };
+        let local_y = Var { name: "y".to_string(), slot: Some(Slot::Local(1)) };
+        assert_eq!(call.get(&local_x), Some(Value::Int(1)));
+        assert_eq!(call.lookup("y"), Some(Value::Int(3)));
+        assert!(call.assign(&local_y, Value::Int(4)).is_err());
+
+        call.assign(&local_x, Value::Int(5)).unwrap();
+        assert_eq!(call.get(&local_x), Some(Value::Int(5)));
+        assert_eq!(env.get(&x), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn calls_are_freed_when_they_return() {
+        let env = Env::default();
+        let mut func = Function::new(Vec::new(), Vec::new());
+        func.locals = vec!["f".to_string()];
+        func.captures = vec![Slot::Local(0)];
+        let func = Rc::new(func);
+
+        // A call that keeps a function made in it, which was enough for a cycle
+        // when functions held on to the scope they were made in
+        let mut call = env.call(None, func.clone()).unwrap();
+        let f = Var { name: "f".to_string(), slot: Some(Slot::Local(0)) };
+        let value = Value::Func(Some(call.closure(&func)), func.clone());
+        call.declare(&f, value, None).unwrap();
+
+        let scope = Rc::downgrade(call.scope.as_ref().unwrap());
+        drop(call);
+        assert!(scope.upgrade().is_none());
+    }
+}
@@ -0,0 +1,266 @@
+use std::cell::{Cell, RefCell};
+use std::mem::size_of;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ast::Value;
+
+// Limits on what a script can use, so a host can run one it doesn't trust
+// without it hanging or running out of memory. Like the output streams,
+// they're per thread, so builtins can check them without being passed anything.
+//
+// A step is a statement, or one more check of a `while` condition, so both
+// engines take the same number of them. Memory is the total size of every
+// string, array and dict made, whether or not it's still in use, which is
+// cheap to keep track of and can only overestimate. Ints too big for an i64
+// count as well, since arithmetic on them can make one of any size.
+//
+// Once a limit has been hit, every step after it fails too, so the script
+// stops even if something between it and the host drops the error.
+//
+// Steps are counted in batches: a step only looks at the limits when the
+// steps handed out last time have run out, which is every 1024 steps, or
+// sooner if the step limit comes first or another limit has been hit.
+
+/// What a script is allowed to use. `None` means no limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub max_memory: Option<usize>, // In bytes
+    pub max_length: Option<usize>, // Of any one string (in characters), array or dict
+}
+
+/// The limit a script was stopped by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exceeded {
+    Steps,
+    Time,
+    Memory,
+    Length,
+}
+
+struct Usage {
+    limits: Limits,
+    deadline: Option<Instant>,
+    steps: u64, // Counting the ones handed out in `FREE_STEPS`
+    memory: usize,
+    made: u64, // How many values have been counted, whether or not they fit
+    exceeded: Option<Exceeded>,
+}
+
+thread_local! {
+    static USAGE: RefCell<Usage> = RefCell::new(Usage::new(Limits::default()));
+    // How many more steps can be taken before one has to look at `USAGE`
+    static FREE_STEPS: Cell<u64> = const { Cell::new(0) };
+}
+
+impl Usage {
+    fn new(limits: Limits) -> Usage {
+        Usage {
+            deadline: limits.timeout.and_then(|timeout| Instant::now().checked_add(timeout)),
+            limits: limits,
+            steps: 0,
+            memory: 0,
+            made: 0,
+            exceeded: None,
+        }
+    }
+
+    fn exceed(&mut self, limit: Exceeded) -> String {
+        self.exceeded = Some(limit);
+        FREE_STEPS.with(|free| free.set(0));
+        let limits = &self.limits;
+        match limit {
+            Exceeded::Steps => format!("step limit of {} exceeded", limits.max_steps.unwrap()),
+            Exceeded::Time => format!("time limit of {}s exceeded", limits.timeout.unwrap().as_secs_f64()),
+            Exceeded::Memory => format!("memory limit of {} bytes exceeded", limits.max_memory.unwrap()),
+            Exceeded::Length => format!("length limit of {} exceeded", limits.max_length.unwrap()),
+        }
+    }
+
+    // Counts a step once the free ones have run out, and hands out some more.
+    fn step(&mut self) -> Result<(), String> {
+        if let Some(limit) = self.exceeded {
+            return Err(self.exceed(limit));
+        }
+
+        self.steps += 1;
+        if self.limits.max_steps.map_or(false, |max| self.steps > max) {
+            return Err(self.exceed(Exceeded::Steps));
+        }
+        // Looking at the clock takes longer than most steps
+        if self.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return Err(self.exceed(Exceeded::Time));
+        }
+
+        let free = self.limits.max_steps.map_or(1023, |max| (max - self.steps).min(1023));
+        self.steps += free;
+        FREE_STEPS.with(|f| f.set(free));
+        Ok(())
+    }
+
+    fn check(&mut self, bytes: usize, length: usize) -> Result<(), String> {
+        if self.limits.max_length.map_or(false, |max| length > max) {
+            return Err(self.exceed(Exceeded::Length));
+        }
+        if self.limits.max_memory.map_or(false, |max| self.memory.saturating_add(bytes) > max) {
+            return Err(self.exceed(Exceeded::Memory));
+        }
+        Ok(())
+    }
+}
+
+/// Sets the limits for the script about to run on this thread, and starts
+/// counting from nothing.
+pub fn set(limits: Limits) {
+    USAGE.with(|usage| *usage.borrow_mut() = Usage::new(limits));
+    FREE_STEPS.with(|free| free.set(0));
+}
+
+/// Starts counting from nothing again, keeping the limits. Called once a
+/// script is ready to run, so literals and constants worked out before it
+/// starts don't count, though the time they took still does.
+pub fn restart() {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let deadline = usage.deadline;
+        *usage = Usage::new(usage.limits.clone());
+        usage.deadline = deadline;
+    });
+    FREE_STEPS.with(|free| free.set(0));
+}
+
+/// Which limit stopped the script, if one did. A host can use this to tell
+/// a script that was stopped from one that failed.
+pub fn exceeded() -> Option<Exceeded> {
+    USAGE.with(|usage| usage.borrow().exceeded)
+}
+
+/// Counts a step, failing if the script has run out of steps or time.
+#[inline]
+pub fn step() -> Result<(), String> {
+    let free = FREE_STEPS.with(Cell::get);
+    if free > 0 {
+        FREE_STEPS.with(|f| f.set(free - 1));
+        return Ok(());
+    }
+    USAGE.with(|usage| usage.borrow_mut().step())
+}
+
+/// Fails if the script has run out of time. For work that can take a long
+/// time between two steps, like multiplying huge ints.
+pub fn check_time() -> Result<(), String> {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        if usage.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return Err(usage.exceed(Exceeded::Time));
+        }
+        Ok(())
+    })
+}
+
+/// Fails if a string, array or dict of this size would go over a limit. For
+/// builtins to call before making one whose size comes from an argument.
+pub fn check(bytes: usize, length: usize) -> Result<(), String> {
+    USAGE.with(|usage| usage.borrow_mut().check(bytes, length))
+}
+
+/// How many bytes of text there's room for before a limit is hit, if there
+/// is a limit. For reading text whose size isn't known until it's all read.
+pub fn room() -> Option<usize> {
+    USAGE.with(|usage| {
+        let usage = usage.borrow();
+        let memory = usage.limits.max_memory.map(|max| max.saturating_sub(usage.memory));
+        // A character is at most four bytes
+        let length = usage.limits.max_length.map(|max| max.saturating_mul(4));
+        match (memory, length) {
+            (Some(memory), Some(length)) => Some(memory.min(length)),
+            (memory, length) => memory.or(length),
+        }
+    })
+}
+
+/// Counts a new string, array, dict or big int. If it goes over a limit, the
+/// script stops at its next step.
+pub fn track(value: &Value) {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        if usage.limits.max_memory.is_none() && usage.limits.max_length.is_none() {
+            return;
+        }
+
+        let (bytes, length) = match *value {
+            // Counting characters takes a while, and there can't be more of them than bytes
+            Value::String(ref s) if usage.limits.max_length.map_or(true, |max| s.len() <= max) => (s.len(), 0),
+            Value::String(ref s) => (s.len(), s.chars().count()),
+            Value::Array(ref vec) => (vec.len() * size_of::<Value>(), vec.len()),
+            Value::Dict(ref dict) => {
+                let keys = dict.keys().map(|key| key.len()).sum::<usize>();
+                (keys + dict.len() * size_of::<(String, Value)>(), dict.len())
+            },
+            Value::BigInt(ref b) => (b.size(), 0),
+            _ => return,
+        };
+        usage.made += 1;
+        if usage.check(bytes, length).is_ok() {
+            usage.memory += bytes;
+        }
+    })
+}
+
+/// How many strings, arrays, dicts and big ints have been counted. A value
+/// worked out without making any can be used again without the limits
+/// seeing any difference.
+pub fn made() -> u64 {
+    USAGE.with(|usage| usage.borrow().made)
+}
+
+/// Sleeps for `duration`, or fails once the time limit is up if that comes first.
+pub fn sleep(duration: Duration) -> Result<(), String> {
+    let deadline = USAGE.with(|usage| usage.borrow().deadline);
+    match deadline {
+        Some(deadline) if Instant::now().checked_add(duration).map_or(true, |end| end > deadline) => {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            USAGE.with(|usage| Err(usage.borrow_mut().exceed(Exceeded::Time)))
+        },
+        _ => {
+            thread::sleep(duration);
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleeping_stops_at_the_time_limit() {
+        set(Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() });
+        let start = Instant::now();
+        assert_eq!(sleep(Duration::from_secs(60)), Err("time limit of 0.02s exceeded".to_string()));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(exceeded(), Some(Exceeded::Time));
+        assert_eq!(step(), Err("time limit of 0.02s exceeded".to_string()));
+        set(Limits::default());
+        assert_eq!(step(), Ok(()));
+    }
+
+    #[test]
+    fn steps_stop_at_the_limit_though_counted_in_batches() {
+        set(Limits { max_steps: Some(3000), ..Limits::default() });
+        for _ in 0..3000 {
+            assert_eq!(step(), Ok(()));
+        }
+        assert_eq!(step(), Err("step limit of 3000 exceeded".to_string()));
+        assert_eq!(exceeded(), Some(Exceeded::Steps));
+
+        // A limit hit between steps stops the next one, however many were handed out
+        set(Limits { max_memory: Some(10), ..Limits::default() });
+        assert_eq!(step(), Ok(()));
+        track(&Value::string("more than ten bytes"));
+        assert_eq!(step(), Err("memory limit of 10 bytes exceeded".to_string()));
+        set(Limits::default());
+    }
+}
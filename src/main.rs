@@ -3,12 +3,17 @@ extern crate itertools;
 extern crate liner;
 use liner::{Buffer, Context, KeyBindings};
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::env::args_os;
 use std::fs::File;
-use std::io::{Read, Write, stdout};
+use std::io::Read;
 use std::path::Path;
 use std::process::exit;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 
 mod parser;
 use parser::*;
@@ -16,54 +21,188 @@ use parser::*;
 mod ast;
 use ast::*;
 
-type VarMap = HashMap<String, Value>;
+mod bigint;
+use bigint::BigInt;
 
-fn main() {
-    let args = args_os().collect::<Vec<_>>();
-    let parameters = args.iter().map(|s| s.to_string_lossy().into_owned()).collect::<Vec<_>>();
-    let exit_val;
+mod decimal;
+use decimal::Decimal;
 
-    match args.len() {
-        0 | 1 => {
-            exit_val = repl();
-        },
-        _ => {
-            let filename = &args[1].clone();
-            exit_val = run_script(filename, parameters);
-        },
+mod number;
+
+mod builtins;
+
+mod output;
+
+mod limits;
+use limits::Limits;
+
+mod env;
+mod resolver;
+mod optimizer;
+use env::{Closure, Env, Origin, DEFAULT_MAX_DEPTH};
+
+mod compiler;
+mod vm;
+
+/// How scripts are run. The two give the same results, so either can be used
+/// to check the other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Engine {
+    Tree, // Walks the syntax tree
+    Bytecode, // Compiles to bytecode for a stack machine, which is much faster
+}
+
+// The tree walker uses the native stack for every call, so the interpreter
+// runs on a thread with this much stack for each call allowed, plus the rest.
+const STACK_PER_CALL: usize = 64 * 1024;
+const BASE_STACK: usize = 8 * 1024 * 1024;
+
+struct Options {
+    seed: Option<u64>,
+    engine: Engine,
+    max_depth: usize,
+    dump_ast: bool, // Print the syntax tree before and after optimizing it, instead of running the script
+    limits: Limits,
+    script: Option<String>,
+    arguments: Vec<String>, // ARGV: the interpreter, the script and the script's arguments
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut iter = args.into_iter();
+    let mut options = Options {
+        seed: None,
+        engine: Engine::Bytecode,
+        max_depth: DEFAULT_MAX_DEPTH,
+        dump_ast: false,
+        limits: Limits::default(),
+        script: None,
+        arguments: iter.next().into_iter().collect(),
+    };
+
+    while let Some(arg) = iter.next() {
+        match &*arg {
+            "--seed" => {
+                let seed = iter.next().ok_or("--seed requires a value".to_string())?;
+                let seed = seed.parse().map_err(|_| format!("invalid seed: {}", seed))?;
+                options.seed = Some(seed);
+            },
+            "--engine" => {
+                options.engine = match &*iter.next().ok_or("--engine requires a value".to_string())? {
+                    "tree" => Engine::Tree,
+                    "bytecode" => Engine::Bytecode,
+                    other => return Err(format!("unknown engine: {} (expected tree or bytecode)", other)),
+                };
+            },
+            "--max-depth" => {
+                let depth = iter.next().ok_or("--max-depth requires a value".to_string())?;
+                options.max_depth = depth.parse().map_err(|_| format!("invalid maximum depth: {}", depth))?;
+            },
+            "--dump-ast" => options.dump_ast = true,
+            "--max-steps" => options.limits.max_steps = Some(parse_value(&arg, iter.next())?),
+            "--timeout" => {
+                let secs = parse_value(&arg, iter.next())?;
+                let timeout = Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid value for --timeout: {}", secs))?;
+                options.limits.timeout = Some(timeout);
+            },
+            "--max-memory" => options.limits.max_memory = Some(parse_value(&arg, iter.next())?),
+            "--max-length" => options.limits.max_length = Some(parse_value(&arg, iter.next())?),
+            _ => {
+                options.script = Some(arg.clone());
+                options.arguments.push(arg);
+                options.arguments.extend(iter);
+                break;
+            },
+        }
     }
-    exit(exit_val);
+
+    Ok(options)
+}
+
+// The value given after `flag`, like the 100 in `--max-steps 100`.
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} requires a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value for {}: {}", flag, value))
 }
 
-fn run_script<P: AsRef<Path>>(path: P, arguments: Vec<String>) -> i32 {
-    let path = path.as_ref();
-    let mut file = match File::open(path) {
-        Ok(file) => file,
+fn main() {
+    let args = args_os().map(|s| s.to_string_lossy().into_owned()).collect::<Vec<_>>();
+    let options = match parse_options(args) {
+        Ok(options) => options,
         Err(e) => {
             eprintln!("Error: {}", e);
-            return 1;
+            exit(1);
         },
     };
 
+    exit(start(options));
+}
+
+// Runs the interpreter on a thread of its own, returning its exit status.
+fn start(options: Options) -> i32 {
+    let max_depth = options.max_depth;
+    let dump_ast = options.dump_ast;
+    let stack_size = max_depth.saturating_mul(STACK_PER_CALL).saturating_add(BASE_STACK);
+    let interpreter = thread::Builder::new().stack_size(stack_size).spawn(move || {
+        // Like the limits, the random numbers are per thread
+        if let Some(seed) = options.seed {
+            builtins::seed_random(seed);
+        }
+        let exit_val = match options.script {
+            None => repl(options.engine, max_depth, &options.limits),
+            Some(ref filename) if dump_ast => dump_script(filename),
+            Some(ref filename) => {
+                limits::set(options.limits.clone());
+                run_script(filename, options.arguments.clone(), options.engine, max_depth)
+            },
+        };
+        output::flush();
+        exit_val
+    });
+    match interpreter {
+        Ok(interpreter) => interpreter.join().unwrap_or(101), // A panic has already been reported
+        Err(e) => {
+            eprintln!("Error: cannot set up a stack for a call depth of {}: {}", max_depth, e);
+            1
+        },
+    }
+}
+
+fn global_vars(max_depth: usize) -> Env {
+    let mut global_vars = Env::from(builtins::constants());
+    global_vars.set_max_depth(max_depth);
+    global_vars
+}
+
+fn read_script<P: AsRef<Path>>(path: P) -> Result<String, String> {
+    let mut file = File::open(path.as_ref()).map_err(|e| e.to_string())?;
     let mut buf = String::new();
-    match file.read_to_string(&mut buf) {
-        Ok(_) => {},
+    file.read_to_string(&mut buf).map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+fn run_script<P: AsRef<Path>>(path: P, arguments: Vec<String>, engine: Engine, max_depth: usize) -> i32 {
+    let buf = match read_script(path) {
+        Ok(buf) => buf,
         Err(e) => {
             eprintln!("Error: {}", e);
             return 1;
         },
-    }
+    };
 
     match ast(&buf) {
         Ok(statements) => {
-            let mut global_vars = HashMap::new();
-            let arg_values = arguments.into_iter().map(|s| Value::String(s)).collect();
-            global_vars.insert("ARGV".to_owned(), Value::Array(arg_values));
-            for s in statements {
-                if let Err(e) = run_statement(&mut global_vars, s) {
-                    eprintln!("Error: {}", e);
-                    return 1;
+            let mut global_vars = global_vars(max_depth);
+            let arg_values = arguments.into_iter().map(Value::string).collect();
+            global_vars.define("ARGV", Value::array(arg_values), Some(Origin::Interpreter));
+            if let Err(e) = run(engine, &mut global_vars, statements) {
+                // Nobody is reading any more, so there's nothing to report
+                if output::closed() {
+                    return 0;
                 }
+                output::flush();
+                eprintln!("Error: {}", e);
+                // A script that was stopped by a limit exits differently from one that failed
+                return if limits::exceeded().is_some() { 2 } else { 1 };
             }
             return 0;
         },
@@ -74,81 +213,97 @@ fn run_script<P: AsRef<Path>>(path: P, arguments: Vec<String>) -> i32 {
     }
 }
 
-fn run_statement(mut global_vars: &mut VarMap, statement: Statement) -> Result<Option<Value>, String> {
-    match statement {
-        Statement::DeclareVar(name, expr) => {
-            let name = Ident::new(name)?.0;
-            let value = eval_expr(&global_vars, &expr)?;
-            global_vars.insert(name, value);
+// Prints the syntax tree of a script once it's been resolved, and again once
+// it's been optimized.
+fn dump_script<P: AsRef<Path>>(path: P) -> i32 {
+    let mut statements = match read_script(path).map(|buf| ast(&buf)) {
+        Ok(Ok(statements)) => statements,
+        Ok(Err(e)) => {
+            eprintln!("Syntax error: {}", e);
+            return 1;
         },
-        Statement::MutateVar(op, name, expr) => {
-            let name = Ident::new(name)?.0;
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        },
+    };
 
-            if let None = global_vars.get(&name) {
-                return Err(format!("undeclared variable: {}", name));
+    if let Err(e) = resolver::resolve(&mut global_vars(DEFAULT_MAX_DEPTH), &mut statements) {
+        eprintln!("Error: {}", e);
+        return 1;
+    }
+    println!("Before optimizing:\n{:#?}\n", statements);
+    optimizer::optimize(&mut statements);
+    println!("After optimizing:\n{:#?}", statements);
+    0
+}
+
+fn run(engine: Engine, global_vars: &mut Env, mut statements: Vec<Statement>) -> Result<(), String> {
+    resolver::resolve(global_vars, &mut statements)?;
+    optimizer::optimize(&mut statements);
+    limits::restart();
+    match engine {
+        Engine::Tree => {
+            for s in &statements {
+                run_statement(global_vars, s)?;
             }
+            Ok(())
+        },
+        Engine::Bytecode => vm::run(global_vars, &statements),
+    }
+}
 
-            let old_value = global_vars.get(&name).unwrap().clone();
-            let rhs = eval_expr(&global_vars, &expr)?;
+fn eval(engine: Engine, global_vars: &mut Env, mut expr: Expr) -> Result<Value, String> {
+    resolver::resolve_expr(global_vars, &mut expr)?;
+    optimizer::optimize_expr(&mut expr);
+    limits::restart();
+    match engine {
+        Engine::Tree => eval_expr(global_vars, &expr),
+        Engine::Bytecode => vm::eval(global_vars, &expr),
+    }
+}
 
-            match op {
-                AssignOp::Equals => {
-                    global_vars.insert(name, rhs);
-                },
-                _ => {
-                    if let (&Value::Num(old), &Value::Num(new)) = (&old_value, &rhs) {
-                        let new_value;
-                        match op {
-                            AssignOp::AddEq => {
-                                new_value = old + new;
-                            },
-                            AssignOp::SubEq => {
-                                new_value = old - new;
-                            },
-                            AssignOp::MulEq => {
-                                new_value = old * new;
-                            },
-                            AssignOp::DivEq => {
-                                new_value = old / new;
-                            },
-                            AssignOp::ModEq => {
-                                new_value = old % new;
-                            },
-                            AssignOp::ExpEq => {
-                                new_value = old.powf(new);
-                            },
-                            _ => unreachable!(),
-                        }
-                        global_vars.insert(name, Value::Num(new_value));
-                    } else {
-                        return Err(format!("= is the only valid assignment operator for {}", old_value.get_type()));
-                    }
-                },
-            }
+fn run_statement(global_vars: &mut Env, statement: &Statement) -> Result<Option<Value>, String> {
+    limits::step()?;
+    match *statement {
+        Statement::DeclareVar(ref pattern, ref expr) => {
+            let value = eval_expr(global_vars, expr)?;
+            declare(global_vars, pattern, value, None)?;
+        },
+        Statement::DeclareConst(ref pattern, ref expr, line) => {
+            let value = eval_expr(global_vars, expr)?;
+            declare(global_vars, pattern, value, Some(line))?;
         },
-        Statement::Expression(expression) => {
-            eval_expr(&global_vars, &expression)?;
+        Statement::MutateVar(op, ref names, ref exprs) => {
+            check_assign_targets(global_vars, names)?;
+
+            // Every value is worked out before anything is assigned, so `a, b = b, a;` swaps
+            let values = exprs.iter().map(|expr| eval_expr(global_vars, expr)).collect::<Result<Vec<Value>, _>>()?;
+            assign(global_vars, &op, names, values)?;
+        },
+        Statement::Expression(ref expression) => {
+            eval_expr(global_vars, expression)?;
         }
-        Statement::Return(expression) => {
-            let return_val = eval_expr(&global_vars, &expression)?;
+        Statement::Return(ref expression) => {
+            let return_val = eval_expr(global_vars, expression)?;
             return Ok(Some(return_val));
         }
-        Statement::If(if_s, elif_s, else_s) => {
-            let if_cond = eval_expr(&global_vars, &if_s.e)?;
+        Statement::If(ref if_s, ref elif_s, ref else_s) => {
+            let if_cond = eval_expr(global_vars, &if_s.e)?;
             if let Value::Boolean(b) = if_cond {
                 if b {
-                    for s in if_s.s {
-                        if let Some(return_val) = run_statement(&mut global_vars, s)? {
+                    for s in &if_s.s {
+                        if let Some(return_val) = run_statement(global_vars, s)? {
                             return Ok(Some(return_val));
                         }
                     }
                     return Ok(None);
-                } else if let Some(statements) = elif_s {
+                } else if let Some(ref statements) = *elif_s {
                     'else_if: for statement in statements {
-                        if let Value::Boolean(b) = eval_expr(&global_vars, &statement.e)? {
+                        if let Value::Boolean(b) = eval_expr(global_vars, &statement.e)? {
                             if b {
-                                for s in statement.s {
-                                    if let Some(return_val) = run_statement(&mut global_vars, s)? {
+                                for s in &statement.s {
+                                    if let Some(return_val) = run_statement(global_vars, s)? {
                                         return Ok(Some(return_val));
                                     }
                                 }
@@ -159,9 +314,9 @@ fn run_statement(mut global_vars: &mut VarMap, statement: Statement) -> Result<O
                         }
                     }
                 }
-                if let Some(statements) = else_s {
+                if let Some(ref statements) = *else_s {
                     for s in statements {
-                        if let Some(return_val) = run_statement(&mut global_vars, s)? {
+                        if let Some(return_val) = run_statement(global_vars, s)? {
                             return Ok(Some(return_val));
                         }
                     }
@@ -171,13 +326,17 @@ fn run_statement(mut global_vars: &mut VarMap, statement: Statement) -> Result<O
                 return Err(format!("expected boolean, found {}", if_cond.get_type()));
             }
         },
-        Statement::While(condition, statements) => {
+        Statement::While(ref condition, ref statements, ref hoisted) => {
+            if !hoisted.is_empty() {
+                global_vars.forget_hoisted(hoisted.clone());
+            }
             loop {
-                let condition = eval_expr(&global_vars, &condition)?;
+                limits::step()?;
+                let condition = eval_expr(global_vars, condition)?;
                 if let Value::Boolean(b) = condition {
                     if b {
-                        for s in statements.clone() {
-                            run_statement(&mut global_vars, s)?;
+                        for s in statements {
+                            run_statement(global_vars, s)?;
                         }
                     } else {
                         break;
@@ -187,313 +346,649 @@ fn run_statement(mut global_vars: &mut VarMap, statement: Statement) -> Result<O
                 }
             }
         },
-        Statement::Print(exprs) => {
-            let values = exprs.into_iter().map(|expr| eval_expr(&global_vars, &expr)).collect::<Result<Vec<Value>, _>>()?;
-            for val in values {
-                let _ = stdout().write_all(val.to_string().as_bytes());
-            }
-            let _ = stdout().flush();
+        Statement::Print(ref exprs) => {
+            let text = print_text(global_vars, "print", exprs)?;
+            print("print", text)?;
+        },
+        Statement::Println(ref exprs) => {
+            let text = print_text(global_vars, "println", exprs)?;
+            print("println", text)?;
         },
-        Statement::Println(exprs) => {
-            let values = exprs.into_iter().map(|expr| eval_expr(&global_vars, &expr)).collect::<Result<Vec<Value>, _>>()?;
-            for val in values {
-                let _ = stdout().write_all(format!("{}", val).as_bytes());
+        Statement::EPrint(ref exprs) => {
+            let text = print_text(global_vars, "eprint", exprs)?;
+            print("eprint", text)?;
+        },
+        Statement::EPrintln(ref exprs) => {
+            let text = print_text(global_vars, "eprintln", exprs)?;
+            print("eprintln", text)?;
+        },
+        Statement::Exit(ref e) => {
+            let status = eval_expr(global_vars, e)?;
+            exit_with(&status);
+        },
+    }
+
+    Ok(None)
+}
+
+// Binds the names in `pattern`, as constants declared on `line` if there is one.
+fn declare(global_vars: &mut Env, pattern: &Pattern, value: Value, line: Option<usize>) -> Result<(), String> {
+    // Nothing is bound unless the whole pattern matches
+    let mut bindings = Vec::new();
+    bind_pattern(pattern, value, &mut bindings)?;
+    for &(var, _) in &bindings {
+        global_vars.check_declare(var)?;
+    }
+    for (var, value) in bindings {
+        global_vars.declare(var, value, line.map(Origin::Line))?;
+    }
+
+    Ok(())
+}
+
+// Matches `value` against a `let` pattern, adding what each name is bound to.
+fn bind_pattern<'a>(pattern: &'a Pattern, value: Value, bindings: &mut Vec<(&'a Var, Value)>) -> Result<(), String> {
+    match *pattern {
+        Pattern::Name(ref var) | Pattern::Rest(ref var) => {
+            if bindings.iter().any(|&(bound, _)| bound.name == var.name) {
+                return Err(format!("{} is bound more than once in the same let", var.name));
             }
-            let _ = stdout().write_all("\n".as_bytes());
-            let _ = stdout().flush();
+            bindings.push((var, value));
         },
-        Statement::Exit(e) => {
-            let status = eval_expr(&global_vars, &e)?;
-            if let Value::Num(exit_val) = status {
-                exit(exit_val as i32);
-            } else {
-                eprintln!("tried to exit with {} (number required)", status.get_type());
-                exit(0);
+        Pattern::Array(ref items) => {
+            let values = match value {
+                Value::Array(values) => Rc::unwrap_or_clone(values),
+                _ => return Err(format!("cannot destructure {} into {}", value.get_type(), pattern)),
+            };
+
+            let rest = items.iter().position(|item| if let Pattern::Rest(_) = *item { true } else { false });
+            if items.iter().filter(|item| if let Pattern::Rest(_) = **item { true } else { false }).count() > 1 {
+                return Err(format!("{} has more than one rest pattern", pattern));
+            }
+            let fits = match rest {
+                Some(_) => values.len() >= items.len() - 1,
+                None => values.len() == items.len(),
+            };
+            if !fits {
+                return Err(format!("cannot destructure array of length {} into {}", values.len(), pattern));
+            }
+
+            // The rest pattern takes whatever the items before and after it don't
+            let mut values = values.into_iter();
+            for (i, item) in items.iter().enumerate() {
+                if Some(i) == rest {
+                    let taken = values.len() - (items.len() - 1 - i);
+                    let rest_values = values.by_ref().take(taken).collect();
+                    bind_pattern(item, Value::array(rest_values), bindings)?;
+                } else {
+                    bind_pattern(item, values.next().unwrap(), bindings)?;
+                }
             }
         },
     }
 
-    Ok(None)
+    Ok(())
+}
+
+fn check_assign_targets(global_vars: &Env, vars: &[Var]) -> Result<(), String> {
+    for var in vars {
+        global_vars.check_assign(var)?;
+    }
+    Ok(())
+}
+
+// `names op= values;`, once the values have been worked out. A single array
+// is spread over several names.
+fn assign(global_vars: &mut Env, op: &AssignOp, names: &[Var], mut values: Vec<Value>) -> Result<(), String> {
+    if names.len() > 1 && values.len() == 1 {
+        values = match values.pop().unwrap() {
+            Value::Array(items) => Rc::unwrap_or_clone(items),
+            other => return Err(format!("cannot assign {} to {} variables", other.get_type(), names.len())),
+        };
+    }
+    if values.len() != names.len() {
+        return Err(format!(
+            "cannot assign {} value{} to {} variable{}",
+            values.len(),
+            if values.len() == 1 { "" } else { "s" },
+            names.len(),
+            if names.len() == 1 { "" } else { "s" },
+        ));
+    }
+
+    if names.len() == 1 {
+        // The usual `x op= value;`, which changes `x` where it is
+        let rhs = values.pop().unwrap();
+        return global_vars.update(&names[0], |value| assigned_value(op, value, rhs));
+    }
+
+    let mut new_values = Vec::new();
+    for (name, rhs) in names.iter().zip(values) {
+        new_values.push(assigned_value(op, &global_vars.get(name).unwrap(), rhs)?);
+    }
+    for (name, value) in names.iter().zip(new_values) {
+        global_vars.assign(name, value)?;
+    }
+
+    Ok(())
+}
+
+// What `name op= rhs;` sets `name` to, when `name` is `value`.
+fn assigned_value(op: &AssignOp, value: &Value, rhs: Value) -> Result<Value, String> {
+    let op = match *op {
+        AssignOp::Equals => return Ok(rhs),
+        AssignOp::AddEq => Op::Add,
+        AssignOp::SubEq => Op::Sub,
+        AssignOp::MulEq => Op::Mul,
+        AssignOp::DivEq => Op::Div,
+        AssignOp::ModEq => Op::Mod,
+        AssignOp::ExpEq => Op::Exp,
+        AssignOp::FloorDivEq => Op::FloorDiv,
+        AssignOp::BitAndEq => Op::BitAnd,
+        AssignOp::BitOrEq => Op::BitOr,
+        AssignOp::BitXorEq => Op::BitXor,
+        AssignOp::ShlEq => Op::Shl,
+        AssignOp::ShrEq => Op::Shr,
+    };
+    binary_op(&op, value, &rhs)
+}
+
+// `print "{} and {}", a, b;` formats like the `format` builtin when the first
+// expression is a string literal containing braces and others follow it.
+// Otherwise the values are printed one after another, so `print "{";` prints
+// a brace.
+fn print_text(global_vars: &Env, statement: &str, exprs: &[Expr]) -> Result<String, String> {
+    let values = exprs.iter().map(|expr| eval_expr(global_vars, expr)).collect::<Result<Vec<Value>, _>>()?;
+    format_values(global_vars, statement, &values, is_format(exprs))
+}
+
+fn is_format(exprs: &[Expr]) -> bool {
+    match exprs.first() {
+        Some(&Expr::Literal(Value::String(ref fmt))) if exprs.len() > 1 => fmt.contains('{') || fmt.contains('}'),
+        _ => false,
+    }
+}
+
+fn format_values(global_vars: &Env, statement: &str, values: &[Value], format: bool) -> Result<String, String> {
+    if let (true, Some(&Value::String(ref fmt))) = (format, values.first()) {
+        return builtins::format_string(fmt, &values[1..], &|name| global_vars.lookup(name))
+            .map_err(|e| format!("{}: {}", statement, e));
+    }
+
+    Ok(values.iter().map(|val| val.to_string()).collect())
+}
+
+// Writes the text of a `print`, `println`, `eprint` or `eprintln` statement.
+fn print(statement: &str, text: String) -> Result<(), String> {
+    match statement {
+        "print" => output::write_out(statement, &text),
+        "println" => output::write_out(statement, &(text + "\n")),
+        "eprint" => output::write_err(statement, &text),
+        _ => output::write_err(statement, &(text + "\n")),
+    }
 }
 
-fn eval_expr(global_vars: &VarMap, expr: &Expr) -> Result<Value, String> {
+fn exit_with(status: &Value) -> ! {
+    output::flush();
+    if let Some(exit_val) = status.as_float() {
+        exit(exit_val as i32);
+    } else {
+        eprintln!("tried to exit with {} (number required)", status.get_type());
+        exit(0);
+    }
+}
+
+fn eval_expr(global_vars: &Env, expr: &Expr) -> Result<Value, String> {
     match *expr {
         Expr::FuncDef(ref v) => {
-            if let &Value::Func(ref _environment, ref args, ref body) = v {
-                let env = global_vars.clone();
-                Ok(Value::Func(Some(env), args.clone(), body.clone()))
-            } else {
-                unreachable!()
-            }
+            make_func(global_vars, v)
         },
         Expr::Literal(ref v) => {
             Ok(v.to_owned())
         },
-        Expr::Reference(ref r) => {
-            let r = Ident::new(r.to_owned())?.0;
-            global_vars.get(&r).map(|item| item.clone()).ok_or(format!("Undefined variable: {}", r))
+        Expr::Reference(ref var) => {
+            global_vars.get(var).ok_or_else(|| format!("Undefined variable: {}", var.name))
         },
         Expr::Typecast(ref expression, ref new_type) => {
             let var = eval_expr(global_vars, expression)?;
             let new_type = eval_expr(global_vars, new_type)?;
-
-            if let &Value::Type(ref new_t) = &new_type {
-                match var {
-                    Value::Num(n) => {
-                        if let &Type::String = new_t {
-                            return Ok(Value::String(n.to_string()));
-                        }
-                    },
-                    Value::Boolean(b) => {
-                        if let &Type::String = new_t {
-                            return Ok(Value::String(b.to_string()));
-                        }
-                    },
-                    Value::Type(ref t) => {
-                        if let &Type::String = t {
-                            return Ok(Value::String(t.to_string()));
-                        }
-                    },
-                    Value::String(ref s) => {
-                        match new_t {
-                            &Type::Boolean => {
-                                match s.parse::<bool>() {
-                                    Ok(b) => {
-                                        return Ok(Value::Boolean(b));
-                                    },
-                                    Err(e) => {
-                                        return Err(e.to_string());
-                                    },
-                                }
-                            },
-                            &Type::Type => {
-                                match type_ident(&s) {
-                                    Ok(new_t) => {
-                                        return Ok(Value::Type(new_t));
-                                    },
-                                    Err(e) => {
-                                        return Err(e.to_string());
-                                    },
-                                }
-                            }
-                            &Type::Num => {
-                                match s.parse::<f64>() {
-                                    Ok(n) => {
-                                        return Ok(Value::Num(n));
-                                    },
-                                    Err(e) => {
-                                        return Err(e.to_string());
-                                    },
-                                }
-                            },
-                            &Type::Array => {
-                                return Ok(Value::Array(s.chars().map(|c| Value::String(c.to_string())).collect::<Vec<_>>()));
-                            },
-                            _ => {},
-                        }
-                    },
-                    Value::Array(ref _a) => {},
-                    Value::Func(_, _, _) => {},
-                    Value::Void => {},
-                }
-
-                return Err(format!("invalid typecast: {} to {}", var.get_type(), new_type));
-            } else {
-                return Err(format!("expected type, found {}", new_type.get_type()))
-            }
+            cast(var, &new_type)
         },
         Expr::TypeOf(ref expr) => {
             let e = eval_expr(global_vars, expr)?;
             Ok(Value::Type(e.get_type()))
         },
-        Expr::CallFunc(ref f_ident, ref args) => {
-            let func = eval_expr(global_vars, f_ident)?;
-            let passed_args = args.into_iter().map(|expr| eval_expr(&global_vars, &expr)).collect::<Result<Vec<Value>, _>>()?;
-            let mut new_env = global_vars.clone();
-
-            if let Value::Func(env, required_args, statements) = func {
-                let environment = env.unwrap();
-                for (name, value) in environment {
-                    new_env.insert(name, value);
-                }
-
-                let passed_len = passed_args.len();
-                let required_len = required_args.len();
-
-                if passed_len == required_len {
-                    for (passed, declared) in passed_args.iter().zip(required_args.iter()) {
-                        let passed_type = &passed.get_type();
-                        let required_type = &declared.1;
-
-                        if passed_type != required_type {
-                            return Err(format!("wrong type of argument passed to function (expected {}, found {})", required_type, passed_type));
+        Expr::CallFunc(ref f_ident, ref args, ref named) => {
+            if let Expr::Reference(ref var) = **f_ident {
+                if !global_vars.is_defined(var) {
+                    if let Some(builtin) = builtins::lookup(&var.name) {
+                        if let Some(&(ref arg, _)) = named.first() {
+                            return Err(format!("{} doesn't take named arguments (found {})", var.name, arg));
                         }
+                        let passed_args = args.iter().map(|expr| eval_expr(global_vars, expr)).collect::<Result<Vec<Value>, _>>()?;
+                        let arg_names = arg_names(args);
+                        let mut caller = |func, args| call_func(global_vars, func, args, Vec::new());
+                        return builtin(&mut builtins::Context::new(&mut caller, &arg_names, global_vars), passed_args);
                     }
-
-                    for (i, var) in required_args.into_iter().enumerate() {
-                        new_env.insert(var.0, passed_args[i].clone());
-                    }
-
-                    for s in statements {
-                        if let Some(return_val) = run_statement(&mut new_env, s)? {
-                            return Ok(return_val);
-                        }
-                    }
-
-                    Ok(Value::Void)
-                } else {
-                    Err(format!("wrong number of arguments passed to function (expected {}, found {})", required_len, passed_len))
                 }
-            } else {
-                Err(format!("cannot call {} as function", func.get_type()))
             }
+
+            let func = eval_expr(global_vars, f_ident)?;
+            let passed_args = args.iter().map(|expr| eval_expr(global_vars, expr)).collect::<Result<Vec<Value>, _>>()?;
+            let mut named_args = Vec::new();
+            for &(ref name, ref expr) in named {
+                named_args.push((name.clone(), eval_expr(global_vars, expr)?));
+            }
+            call_func(global_vars, func, passed_args, named_args)
         }
         Expr::Array(ref exprs) => {
             let mut array = Vec::new();
             for e in exprs {
                 array.push(eval_expr(global_vars, e)?);
             }
-            Ok(Value::Array(array))
+            Ok(Value::array(array))
+        }
+        Expr::Dict(ref entries) => {
+            let mut dict = BTreeMap::new();
+            for &(ref key, ref e) in entries {
+                dict.insert(key.clone(), eval_expr(global_vars, e)?);
+            }
+            Ok(Value::dict(dict))
         }
         Expr::Index(ref expression, ref index) => {
             let var = eval_expr(global_vars, expression)?;
             let index = eval_expr(global_vars, index)?;
-
-            if let Value::Num(ref i) = index {
-                match var {
-                    Value::Array(ref values) => {
-                        if let Some(item) = values.get(i.clone() as usize) {
-                            Ok(item.clone())
-                        } else {
-                            Err(format!("attempted to access index {} of array with length of {}", index, values.len()))
-                        }
-                    },
-                    _ => {
-                        Err(format!("attempted to index a {}", var.get_type()))
-                    }
-                }
-            } else {
-                Err(format!("{} cannot be used as index", index.get_type()))
-            }
+            index_value(&var, &index)
         },
         Expr::BinOp(ref op, ref left, ref right) => {
             let left = eval_expr(global_vars, left)?;
             let right = eval_expr(global_vars, right)?;
-
-            if let (&Value::Num(n1), &Value::Num(n2)) = (&left, &right) {
-                Ok(Value::Num(
-                    match *op {
-                        Op::Add => n1 + n2,
-                        Op::Sub => n1 - n2,
-                        Op::Mul => n1 * n2,
-                        Op::Div => n1 / n2,
-                        Op::Mod => n1 % n2,
-                        Op::Exp => n1.powf(n2),
-                    }
-                ))
-            } else if let (&Value::String(ref left_val), &Value::String(ref right_val)) = (&left, &right) {
-                let new_val = match *op {
-                    Op::Add => format!("{}{}", left_val, right_val),
-                    _ => {
-                        return Err(format!("invalid operation ({} with {})", left.get_type(), right.get_type()));
-                    },
-                };
-                Ok(Value::String(new_val))
-            } else {
-                Err(format!("invalid operation ({} with {})", left.get_type(), right.get_type()))
-            }
+            binary_op(op, &left, &right)
         },
         Expr::Comparison(ref op, ref left, ref right) => {
             let left = eval_expr(global_vars, left)?;
             let right = eval_expr(global_vars, right)?;
-
-            let compare_bools = |left: f64, right: f64| {
-                match *op {
-                    CompOp::Equal => left == right,
-                    CompOp::NotEq => left != right,
-                    CompOp::Gt => left > right,
-                    CompOp::Ge => left >= right,
-                    CompOp::Lt => left < right,
-                    CompOp::Le => left <= right,
-                }
-            };
-
-            let compare_strs = |left: &str, right: &str| {
-                match *op {
-                    CompOp::Equal => left == right,
-                    CompOp::NotEq => left != right,
-                    CompOp::Gt => left > right,
-                    CompOp::Ge => left >= right,
-                    CompOp::Lt => left < right,
-                    CompOp::Le => left <= right,
-                }
-            };
-
-            if let (&Value::Num(n1), &Value::Num(n2)) = (&left, &right) {
-                Ok(Value::Boolean(compare_bools(n1, n2)))
-            } else if let (&Value::String(ref s1), &Value::String(ref s2)) = (&left, &right) {
-                Ok(Value::Boolean(compare_strs(s1, s2)))
-            } else {
-                Err(format!("invalid comparison ({} with {})", left.get_type(), right.get_type()))
-            }
+            compare(op, &left, &right)
         },
         Expr::BoolChain(ref op, ref left, ref right) => {
             let left = eval_expr(global_vars, left)?;
             let right = eval_expr(global_vars, right)?;
-
-            if let (&Value::Boolean(b1), &Value::Boolean(b2)) = (&left, &right) {
-                Ok(Value::Boolean(
-                    match *op {
-                        BoolLogic::And => b1 && b2,
-                        BoolLogic::Or => b1 || b2,
-                    }
-                ))
-            } else {
-                Err(format!("invalid boolean logic (expected two booleans, found {} and {})", left.get_type(), right.get_type()))
-            }
+            bool_chain(op, &left, &right)
         }
         Expr::UnOp(ref op, ref expr) => {
             let expr = eval_expr(global_vars, expr)?;
-            match *op {
-                UnaryOp::Not => {
-                    if let &Value::Boolean(b) = &expr {
-                        Ok(Value::Boolean(!b))
-                    } else {
-                        Err(format!("cannot negate {}", expr.get_type()))
-                    }
-                },
-            }
+            unary_op(op, &expr)
         }
         Expr::Length(ref expr) => {
             let expr = eval_expr(global_vars, expr)?;
-            if let Value::Array(ref vec) = expr {
-                let len = vec.len();
-                Ok(Value::Num(len as f64))
-            } else {
-                Err(format!("cannot get length of {}", expr.get_type()))
-            }
+            length(&expr)
         }
         Expr::ToUpper(ref expr) => {
             let expr = eval_expr(global_vars, expr)?;
-            if let Value::String(ref s) = expr {
-                Ok(Value::String(s.to_uppercase()))
-            } else {
-                Err(format!("cannot make {} uppercase", expr.get_type()))
-            }
+            to_upper(&expr)
         }
         Expr::ToLower(ref expr) => {
             let expr = eval_expr(global_vars, expr)?;
-            if let Value::String(ref s) = expr {
-                Ok(Value::String(s.to_lowercase()))
+            to_lower(&expr)
+        }
+        Expr::Hoisted(i, ref expr) => {
+            if let Some(value) = global_vars.hoisted(i) {
+                return Ok(value);
+            }
+            let made = limits::made();
+            let value = eval_expr(global_vars, expr)?;
+            if limits::made() == made {
+                global_vars.hoist(i, value.clone());
+            }
+            Ok(value)
+        }
+    }
+}
+
+// The operations below are shared by both engines, so they report the same errors.
+
+// A function value for a `func(...) { ... }` expression. It keeps the
+// variables it uses as they are now.
+fn make_func(global_vars: &Env, definition: &Value) -> Result<Value, String> {
+    if let &Value::Func(_, ref func) = definition {
+        check_params(&func.params)?;
+        Ok(Value::Func(Some(global_vars.closure(func)), func.clone()))
+    } else {
+        unreachable!()
+    }
+}
+
+// The variable name of each argument that is a plain reference, which
+// builtins use to name callbacks in errors.
+fn arg_names(args: &[Expr]) -> Vec<Option<String>> {
+    args.iter().map(|expr| {
+        match *expr {
+            Expr::Reference(ref var) => Some(var.name.clone()),
+            _ => None,
+        }
+    }).collect()
+}
+
+fn cast(value: Value, new_type: &Value) -> Result<Value, String> {
+    if let Value::Type(ref new_t) = *new_type {
+        typecast(value, new_t)
+    } else {
+        Err(format!("expected type, found {}", new_type.get_type()))
+    }
+}
+
+fn index_value(var: &Value, index: &Value) -> Result<Value, String> {
+    if let (&Value::Dict(ref dict), &Value::String(ref key)) = (var, index) {
+        dict.get(&**key).cloned().ok_or_else(|| format!("key not found in dict: {}", key))
+    } else if let Some(n) = index.as_float() {
+        // Whole nums are fine, but there's no element 1.5 or -1
+        let i = match *index {
+            Value::Int(i) if i >= 0 => i as usize,
+            Value::Num(_) if n >= 0.0 && n.fract() == 0.0 => n as usize,
+            _ => return Err(format!("{} cannot be used as index", index)),
+        };
+
+        match *var {
+            Value::Array(ref values) => {
+                if let Some(item) = values.get(i) {
+                    Ok(item.clone())
+                } else {
+                    Err(format!("attempted to access index {} of array with length of {}", index, values.len()))
+                }
+            },
+            _ => {
+                Err(format!("attempted to index a {}", var.get_type()))
+            }
+        }
+    } else {
+        Err(format!("{} cannot be used as index", index.get_type()))
+    }
+}
+
+fn compare(op: &CompOp, left: &Value, right: &Value) -> Result<Value, String> {
+    // None when the values are unordered, as NaN is with everything
+    let ordering = match (left, right) {
+        (&Value::Int(i1), &Value::Int(i2)) => Some(i1.cmp(&i2)),
+        (&Value::String(ref s1), &Value::String(ref s2)) => Some(s1.cmp(s2)),
+        _ if number::is_number(left) && number::is_number(right) => number::compare(left, right)?,
+        _ => return Err(format!("invalid comparison ({} with {})", left.get_type(), right.get_type())),
+    };
+
+    Ok(Value::Boolean(
+        match *op {
+            CompOp::Equal => ordering == Some(Ordering::Equal),
+            CompOp::NotEq => ordering != Some(Ordering::Equal),
+            CompOp::Gt => ordering == Some(Ordering::Greater),
+            CompOp::Ge => ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal),
+            CompOp::Lt => ordering == Some(Ordering::Less),
+            CompOp::Le => ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal),
+        }
+    ))
+}
+
+fn bool_chain(op: &BoolLogic, left: &Value, right: &Value) -> Result<Value, String> {
+    if let (&Value::Boolean(b1), &Value::Boolean(b2)) = (left, right) {
+        Ok(Value::Boolean(
+            match *op {
+                BoolLogic::And => b1 && b2,
+                BoolLogic::Or => b1 || b2,
+            }
+        ))
+    } else {
+        Err(format!("invalid boolean logic (expected two booleans, found {} and {})", left.get_type(), right.get_type()))
+    }
+}
+
+fn unary_op(op: &UnaryOp, value: &Value) -> Result<Value, String> {
+    match *op {
+        UnaryOp::Not => {
+            if let &Value::Boolean(b) = value {
+                Ok(Value::Boolean(!b))
             } else {
-                Err(format!("cannot make {} lowercase", expr.get_type()))
+                Err(format!("cannot negate {}", value.get_type()))
+            }
+        },
+        UnaryOp::Neg => number::negate(value),
+        UnaryOp::BitNot => number::bit_not(value),
+    }
+}
+
+fn length(value: &Value) -> Result<Value, String> {
+    if let Value::Array(ref vec) = *value {
+        let len = vec.len();
+        Ok(Value::Int(len as i64))
+    } else if let Value::String(ref s) = *value {
+        Ok(Value::Int(s.chars().count() as i64))
+    } else if let Value::Dict(ref dict) = *value {
+        Ok(Value::Int(dict.len() as i64))
+    } else {
+        Err(format!("cannot get length of {}", value.get_type()))
+    }
+}
+
+fn to_upper(value: &Value) -> Result<Value, String> {
+    if let Value::String(ref s) = *value {
+        Ok(Value::string(s.to_uppercase()))
+    } else {
+        Err(format!("cannot make {} uppercase", value.get_type()))
+    }
+}
+
+fn to_lower(value: &Value) -> Result<Value, String> {
+    if let Value::String(ref s) = *value {
+        Ok(Value::string(s.to_lowercase()))
+    } else {
+        Err(format!("cannot make {} lowercase", value.get_type()))
+    }
+}
+
+// Casting to a value's own type always works, and everything can be cast to a
+// string, which gives the same text `print` would show.
+fn typecast(value: Value, new_t: &Type) -> Result<Value, String> {
+    if value.get_type() == *new_t {
+        return Ok(value);
+    }
+
+    let not_valid = |s: &str| Err(format!("invalid typecast: \"{}\" is not a valid {}", s, new_t));
+    match (&value, new_t) {
+        (_, &Type::String) => Ok(Value::string(value.to_string())),
+
+        (&Value::Boolean(b), &Type::Num) => Ok(Value::Num(if b { 1.0 } else { 0.0 })),
+        (&Value::Boolean(b), &Type::Int) => Ok(Value::Int(b as i64)),
+        (&Value::Boolean(b), &Type::Decimal) => Ok(Value::Decimal(Decimal::from_bigint(BigInt::from_i64(b as i64)))),
+
+        // Zero is false and anything else, even NaN, is true
+        (_, &Type::Boolean) if number::is_number(&value) => Ok(Value::Boolean(value.as_float() != Some(0.0))),
+        (_, &Type::Num) if number::is_number(&value) => Ok(Value::Num(value.as_float().unwrap())),
+        (&Value::Num(n), &Type::Int) | (&Value::Num(n), &Type::Decimal) => {
+            match Decimal::from_f64(n) {
+                Some(d) => {
+                    if let Type::Decimal = *new_t {
+                        Ok(Value::Decimal(d))
+                    } else {
+                        // Rounds towards zero, like casts in most languages
+                        Ok(number::normalize(d.trunc()))
+                    }
+                },
+                None => Err(format!("invalid typecast: {} has no {} value", n, new_t)),
+            }
+        },
+        (&Value::Int(_), &Type::Decimal) | (&Value::BigInt(_), &Type::Decimal) => {
+            Ok(Value::Decimal(Decimal::parse(&value.to_string()).unwrap()))
+        },
+        (&Value::Decimal(ref d), &Type::Int) => Ok(number::normalize(d.trunc())),
+
+        (&Value::String(ref s), &Type::Boolean) => {
+            match s.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => not_valid(s),
+            }
+        },
+        (&Value::String(ref s), &Type::Num) => s.parse().map(Value::Num).or_else(|_| not_valid(s)),
+        (&Value::String(ref s), &Type::Int) => BigInt::parse(s).map(number::normalize).map_or_else(|| not_valid(s), Ok),
+        (&Value::String(ref s), &Type::Decimal) => Decimal::parse(s).map(Value::Decimal).map_or_else(|| not_valid(s), Ok),
+        (&Value::String(ref s), &Type::Type) => type_ident(s).map(Value::Type).or_else(|_| not_valid(s)),
+        (&Value::String(ref s), &Type::Array) => Ok(Value::array(s.chars().map(|c| Value::string(c.to_string())).collect())),
+
+        // Dicts and arrays of [key, value] pairs convert both ways
+        (&Value::Dict(ref dict), &Type::Array) => {
+            Ok(Value::array(dict.iter().map(|(k, v)| Value::array(vec![Value::string(k.clone()), v.clone()])).collect()))
+        },
+        (&Value::Array(ref pairs), &Type::Dict) => {
+            let mut dict = BTreeMap::new();
+            for pair in pairs.iter() {
+                match *pair {
+                    Value::Array(ref kv) if kv.len() == 2 => {
+                        match kv[0] {
+                            Value::String(ref key) => {
+                                dict.insert(key.to_string(), kv[1].clone());
+                            },
+                            _ => return Err(format!("invalid typecast: dict keys must be strings, found {}", kv[0].get_type())),
+                        }
+                    },
+                    _ => return Err(format!("invalid typecast: expected [key, value] pair, found {}", pair)),
+                }
+            }
+            Ok(Value::dict(dict))
+        },
+
+        _ => Err(format!("invalid typecast: {} to {}", value.get_type(), new_t)),
+    }
+}
+
+fn binary_op(op: &Op, left: &Value, right: &Value) -> Result<Value, String> {
+    match (left, right) {
+        (&Value::String(ref s1), &Value::String(ref s2)) if *op == Op::Add => {
+            Ok(Value::string(format!("{}{}", s1, s2)))
+        },
+        _ if number::is_number(left) && number::is_number(right) => number::arithmetic(op, left, right),
+        _ => Err(format!("invalid operation ({} with {})", left.get_type(), right.get_type())),
+    }
+}
+
+// Parameters with defaults can't be followed by ones without, and a variadic
+// parameter has to come last, so positional arguments always fill them in order.
+fn check_params(params: &[Param]) -> Result<(), String> {
+    for (i, param) in params.iter().enumerate() {
+        if params[..i].iter().any(|p| p.name == param.name) {
+            return Err(format!("parameter {} is declared more than once", param.name));
+        }
+        if param.variadic {
+            if i != params.len() - 1 {
+                return Err(format!("variadic parameter ...{} must be the last parameter", param.name));
+            }
+            if param.t != Type::Array {
+                return Err(format!("variadic parameter ...{} must have type array, found {}", param.name, param.t));
             }
+        } else if param.default.is_none() && params[..i].iter().any(|p| p.default.is_some()) {
+            return Err(format!("parameter {} needs a default value, since a parameter before it has one", param.name));
+        }
+    }
+
+    Ok(())
+}
+
+fn call_func(global_vars: &Env, func: Value, passed_args: Vec<Value>, named_args: Vec<(String, Value)>) -> Result<Value, String> {
+    if let Value::Func(closure, func) = func {
+        let mut new_env = call_env(global_vars, closure, &func, passed_args, named_args, &mut eval_expr)?;
+        for s in &func.body {
+            if let Some(return_val) = run_statement(&mut new_env, s)? {
+                return Ok(return_val);
+            }
+        }
+
+        Ok(Value::Void)
+    } else {
+        Err(format!("cannot call {} as function", func.get_type()))
+    }
+}
+
+// The variables a function body runs with: new locals, starting with the
+// arguments, on top of the ones it was defined with and its caller's.
+// `eval_default` works out default values for parameters that weren't passed.
+fn call_env(
+    global_vars: &Env,
+    closure: Option<Rc<Closure>>,
+    func: &Rc<Function>,
+    passed_args: Vec<Value>,
+    named_args: Vec<(String, Value)>,
+    eval_default: &mut dyn FnMut(&Env, &Expr) -> Result<Value, String>,
+) -> Result<Env, String> {
+    let mut new_env = global_vars.call(closure, func.clone())?;
+    let params = &func.params;
+
+    let variadic = params.last().map_or(false, |p| p.variadic);
+    let fixed = params.len() - variadic as usize;
+    let required = params.iter().filter(|p| p.default.is_none() && !p.variadic).count();
+    let passed_len = passed_args.len();
+
+    if (passed_len > fixed && !variadic) || (passed_len < required && named_args.is_empty()) {
+        let expected = if variadic {
+            format!("at least {}", required)
+        } else if required == fixed {
+            required.to_string()
+        } else {
+            format!("{} to {}", required, fixed)
+        };
+        return Err(format!("wrong number of arguments passed to function (expected {}, found {})", expected, passed_len));
+    }
+
+    if passed_len == fixed && !variadic && named_args.is_empty() {
+        // The usual call, which passes every argument in order
+        for (i, (param, value)) in params.iter().zip(passed_args).enumerate() {
+            check_arg_type(param, &value)?;
+            new_env.bind_param(i, value);
         }
+        return Ok(new_env);
+    }
+
+    // Positional arguments fill parameters in order, and any left over go to the variadic one
+    let mut args = passed_args.into_iter();
+    let mut values: Vec<Option<Value>> = args.by_ref().take(fixed).map(Some).collect();
+    values.resize(fixed, None);
+    if variadic {
+        values.push(Some(Value::array(args.collect())));
     }
+
+    for (name, value) in named_args {
+        match params.iter().position(|p| p.name == name) {
+            Some(i) if params[i].variadic => return Err(format!("variadic parameter ...{} cannot be passed by name", name)),
+            Some(i) if values[i].is_some() => return Err(format!("argument {} passed more than once", name)),
+            Some(i) => values[i] = Some(value),
+            None => return Err(format!("function has no parameter named {}", name)),
+        }
+    }
+
+    // Defaults are evaluated in order, so they can use the parameters before them
+    for (i, (param, value)) in params.iter().zip(values).enumerate() {
+        let value = match (value, &param.default) {
+            (Some(value), _) => value,
+            (None, &Some(ref default)) => eval_default(&new_env, default)?,
+            (None, &None) => return Err(format!("missing argument for parameter {}", param.name)),
+        };
+        check_arg_type(param, &value)?;
+        new_env.bind_param(i, value);
+    }
+
+    Ok(new_env)
 }
 
-fn repl() -> i32 {
-    let mut var_map: VarMap = HashMap::new();
+fn check_arg_type(param: &Param, value: &Value) -> Result<(), String> {
+    let passed_type = &value.get_type();
+    let required_type = &param.t;
+
+    // An int can be passed where a num is expected
+    let int_as_num = *passed_type == Type::Int && *required_type == Type::Num;
+    if passed_type != required_type && !int_as_num {
+        return Err(format!("wrong type of argument passed to function (expected {}, found {})", required_type, passed_type));
+    }
+    Ok(())
+}
+
+// Every line gets the limits to itself.
+fn repl(engine: Engine, max_depth: usize, limits: &Limits) -> i32 {
+    let mut var_map = global_vars(max_depth);
 
     let mut context = Context::new();
     context.completer = None;
@@ -502,20 +997,27 @@ fn repl() -> i32 {
     loop {
         match context.read_line("> ", &mut |_| {}) {
             Ok(line) => {
+                limits::set(limits.clone());
                 match single_line(&line) {
                     Ok(parsed) => {
                         match  parsed {
                             Line::Statement(s) => {
-                                if let Err(e) = run_statement(&mut var_map, s) {
+                                if let Err(e) = run(engine, &mut var_map, vec![s]) {
+                                    if output::closed() {
+                                        break;
+                                    }
                                     println!("Error: {}", e);
                                 }
                             },
                             Line::Expression(e) => {
-                                match eval_expr(&var_map, &e) {
+                                match eval(engine, &mut var_map, e) {
                                     Ok(expr) => {
                                         println!("{}", expr);
                                     },
                                     Err(e) => {
+                                        if output::closed() {
+                                            break;
+                                        }
                                         println!("Error: {}", e);
                                     },
                                 }
@@ -526,6 +1028,7 @@ fn repl() -> i32 {
                         println!("{}", e);
                     },
                 }
+                output::flush();
 
                 let buffer = Buffer::from(line);
                 let _ = context.history.push(buffer);
@@ -538,3 +1041,31 @@ fn repl() -> i32 {
 
     0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn seed_makes_the_script_repeatable() {
+        let dir = temp_dir();
+        let script = dir.join(format!("toylang-seed-{}.toylang", process::id()));
+        let out = dir.join(format!("toylang-seed-{}.txt", process::id()));
+        fs::write(&script, format!("write_file({:?}, random() as string);", out.to_str().unwrap())).unwrap();
+
+        let run = |seed: &str| {
+            let args = ["toylang", "--seed", seed, script.to_str().unwrap()].iter().map(|s| s.to_string()).collect();
+            assert_eq!(start(parse_options(args).unwrap()), 0);
+            fs::read_to_string(&out).unwrap()
+        };
+        let first = run("42");
+        assert_eq!(run("42"), first);
+        assert!(run("43") != first);
+
+        let _ = fs::remove_file(&script);
+        let _ = fs::remove_file(&out);
+    }
+}
@@ -0,0 +1,259 @@
+use std::cmp::Ordering;
+
+use ast::*;
+use bigint::BigInt;
+use decimal::Decimal;
+use limits;
+
+// Arithmetic and comparison between the numeric types. Ints are exact and
+// switch to a big integer when they overflow (and back again when a result
+// fits). Decimals are exact too, and mix with ints but not with nums, since
+// that would quietly bring back the rounding errors they exist to avoid.
+
+pub fn is_number(value: &Value) -> bool {
+    match *value {
+        Value::Num(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) => true,
+        _ => false,
+    }
+}
+
+/// An int, stored as an i64 when it fits.
+pub fn normalize(n: BigInt) -> Value {
+    match n.to_i64() {
+        Some(i) => Value::Int(i),
+        None => {
+            let value = Value::BigInt(n);
+            limits::track(&value);
+            value
+        },
+    }
+}
+
+/// How many bits an int or decimal takes, which is what a power or shift of
+/// it costs to work out. `None` for a num.
+pub fn exact_bits(value: &Value) -> Option<u64> {
+    match *value {
+        Value::Int(i) => Some(64 - i.unsigned_abs().leading_zeros() as u64),
+        Value::BigInt(ref b) => Some(b.bits()),
+        Value::Decimal(ref d) => Some(d.bits()),
+        _ => None,
+    }
+}
+
+fn to_bigint(value: &Value) -> Option<BigInt> {
+    match *value {
+        Value::Int(i) => Some(BigInt::from_i64(i)),
+        Value::BigInt(ref b) => Some(b.clone()),
+        _ => None,
+    }
+}
+
+fn to_decimal(value: &Value) -> Option<Decimal> {
+    match *value {
+        Value::Decimal(ref d) => Some(d.clone()),
+        _ => to_bigint(value).map(Decimal::from_bigint),
+    }
+}
+
+fn is_decimal(value: &Value) -> bool {
+    match *value {
+        Value::Decimal(_) => true,
+        _ => false,
+    }
+}
+
+fn is_bitwise(op: &Op) -> bool {
+    match *op {
+        Op::BitAnd | Op::BitOr | Op::BitXor | Op::Shl | Op::Shr => true,
+        _ => false,
+    }
+}
+
+/// `left op right`, where both are numbers.
+pub fn arithmetic(op: &Op, left: &Value, right: &Value) -> Result<Value, String> {
+    if let (&Value::Int(i1), &Value::Int(i2)) = (left, right) {
+        return int_op(op, i1, i2);
+    }
+    if let (Some(b1), Some(b2)) = (to_bigint(left), to_bigint(right)) {
+        return bigint_op(op, &b1, &b2);
+    }
+    if is_bitwise(op) {
+        return Err(format!("{} needs ints, found {} and {}", op, left.get_type(), right.get_type()));
+    }
+    if is_decimal(left) || is_decimal(right) {
+        return match (to_decimal(left), to_decimal(right)) {
+            (Some(d1), Some(d2)) => decimal_op(op, &d1, &d2, right),
+            _ => Err(format!(
+                "invalid operation ({} with {}), convert one of them with `as`",
+                left.get_type(),
+                right.get_type(),
+            )),
+        };
+    }
+
+    let (n1, n2) = (left.as_float().unwrap(), right.as_float().unwrap());
+    Ok(Value::Num(
+        match *op {
+            Op::Add => n1 + n2,
+            Op::Sub => n1 - n2,
+            Op::Mul => n1 * n2,
+            Op::Div => n1 / n2,
+            Op::Mod => n1 % n2,
+            Op::Exp => n1.powf(n2),
+            Op::FloorDiv => (n1 / n2).floor(),
+            _ => unreachable!(),
+        }
+    ))
+}
+
+// `/` gives a num, since the result is rarely a whole number.
+fn int_op(op: &Op, i1: i64, i2: i64) -> Result<Value, String> {
+    let result = match *op {
+        Op::Add => i1.checked_add(i2),
+        Op::Sub => i1.checked_sub(i2),
+        Op::Mul => i1.checked_mul(i2),
+        Op::Div => return Ok(Value::Num(i1 as f64 / i2 as f64)),
+        Op::Mod => {
+            if i2 == 0 {
+                return Err(format!("integer modulo by zero ({} % 0)", i1));
+            }
+            i1.checked_rem(i2)
+        },
+        Op::Exp => {
+            if i2 < 0 {
+                return Ok(Value::Num((i1 as f64).powf(i2 as f64)));
+            }
+            if i2 > u32::MAX as i64 { None } else { i1.checked_pow(i2 as u32) }
+        },
+        Op::FloorDiv => {
+            if i2 == 0 {
+                return Err(format!("integer division by zero ({} // 0)", i1));
+            }
+            // Rounds towards negative infinity, unlike Rust's `/`
+            i1.checked_div(i2).map(|q| if i1 % i2 != 0 && (i1 < 0) != (i2 < 0) { q - 1 } else { q })
+        },
+        Op::BitAnd => Some(i1 & i2),
+        Op::BitOr => Some(i1 | i2),
+        Op::BitXor => Some(i1 ^ i2),
+        Op::Shl => {
+            if i2 < 0 {
+                return Err(format!("negative shift ({} << {})", i1, i2));
+            }
+            // Overflows if any bits (or the sign) would be lost
+            if i2 < 64 && (i1 << i2) >> i2 == i1 { Some(i1 << i2) } else { None }
+        },
+        Op::Shr => {
+            if i2 < 0 {
+                return Err(format!("negative shift ({} >> {})", i1, i2));
+            }
+            Some(if i2 >= 64 { if i1 < 0 { -1 } else { 0 } } else { i1 >> i2 })
+        },
+    };
+
+    match result {
+        Some(i) => Ok(Value::Int(i)),
+        None => bigint_op(op, &BigInt::from_i64(i1), &BigInt::from_i64(i2)),
+    }
+}
+
+fn bigint_op(op: &Op, b1: &BigInt, b2: &BigInt) -> Result<Value, String> {
+    match *op {
+        Op::Add => Ok(normalize(b1.add(b2))),
+        Op::Sub => Ok(normalize(b1.sub(b2))),
+        Op::Mul => b1.mul(b2).map(normalize),
+        Op::Div => Ok(Value::Num(b1.to_f64() / b2.to_f64())),
+        Op::Mod => match b1.divrem(b2) {
+            Some((_, r)) => Ok(normalize(r)),
+            None => Err(format!("integer modulo by zero ({} % 0)", b1)),
+        },
+        Op::Exp => {
+            if b2.is_negative() {
+                return Ok(Value::Num(b1.to_f64().powf(b2.to_f64())));
+            }
+            // These stay small however large the exponent is
+            match b1.to_i64() {
+                Some(0) | Some(1) => return Ok(normalize(b1.clone())),
+                Some(-1) => {
+                    let even = b2.divrem(&BigInt::from_i64(2)).unwrap().1.is_zero();
+                    return Ok(Value::Int(if even { 1 } else { -1 }));
+                },
+                _ => {},
+            }
+            match b2.to_i64() {
+                Some(exp) => b1.pow(exp as u64).map(normalize),
+                None => Err("integer too large".to_string()),
+            }
+        },
+        Op::FloorDiv => match b1.div_floor(b2) {
+            Some(q) => Ok(normalize(q)),
+            None => Err(format!("integer division by zero ({} // 0)", b1)),
+        },
+        Op::BitAnd => Ok(normalize(b1.bitwise(b2, |x, y| x & y))),
+        Op::BitOr => Ok(normalize(b1.bitwise(b2, |x, y| x | y))),
+        Op::BitXor => Ok(normalize(b1.bitwise(b2, |x, y| x ^ y))),
+        Op::Shl | Op::Shr => {
+            if b2.is_negative() {
+                return Err(format!("negative shift ({} {} {})", b1, op, b2));
+            }
+            let bits = b2.to_i64().map_or(u64::MAX, |b| b as u64);
+            if *op == Op::Shl {
+                if b1.is_zero() { Ok(Value::Int(0)) } else { b1.shl(bits).map(normalize) }
+            } else {
+                Ok(normalize(b1.shr(bits)))
+            }
+        },
+    }
+}
+
+fn decimal_op(op: &Op, d1: &Decimal, d2: &Decimal, right: &Value) -> Result<Value, String> {
+    let result = match *op {
+        Op::Add => d1.add(d2),
+        Op::Sub => d1.sub(d2),
+        Op::Mul => d1.mul(d2),
+        Op::Div => d1.div(d2),
+        Op::Mod => d1.rem(d2),
+        Op::Exp => match to_bigint(right).and_then(|b| b.to_i64()) {
+            Some(exp) => d1.pow(exp),
+            None => Err(format!("a decimal can only be raised to an int power, found {}", right.get_type())),
+        },
+        Op::FloorDiv => d1.div_floor(d2),
+        _ => unreachable!(),
+    };
+    result.map(Value::Decimal)
+}
+
+pub fn negate(value: &Value) -> Result<Value, String> {
+    match *value {
+        Value::Num(n) => Ok(Value::Num(-n)),
+        Value::Int(i) => Ok(i.checked_neg().map(Value::Int).unwrap_or_else(|| normalize(BigInt::from_i64(i).neg()))),
+        Value::BigInt(ref b) => Ok(normalize(b.neg())),
+        Value::Decimal(ref d) => Ok(Value::Decimal(d.neg())),
+        _ => Err(format!("cannot apply unary minus to {}", value.get_type())),
+    }
+}
+
+/// `~x`, which is `-x - 1` for any int.
+pub fn bit_not(value: &Value) -> Result<Value, String> {
+    match *value {
+        Value::Int(i) => Ok(Value::Int(!i)),
+        Value::BigInt(ref b) => Ok(normalize(b.neg().sub(&BigInt::from_i64(1)))),
+        _ => Err(format!("~ needs an int, found {}", value.get_type())),
+    }
+}
+
+/// How two numbers compare, or `None` if one of them is NaN.
+pub fn compare(left: &Value, right: &Value) -> Result<Option<Ordering>, String> {
+    if let (&Value::Int(i1), &Value::Int(i2)) = (left, right) {
+        return Ok(Some(i1.cmp(&i2)));
+    }
+    if let (Some(b1), Some(b2)) = (to_bigint(left), to_bigint(right)) {
+        return Ok(Some(b1.cmp(&b2)));
+    }
+    if is_decimal(left) || is_decimal(right) {
+        return match (to_decimal(left), to_decimal(right)) {
+            (Some(d1), Some(d2)) => Ok(d1.partial_cmp(&d2)),
+            _ => Err(format!("invalid comparison ({} with {})", left.get_type(), right.get_type())),
+        };
+    }
+    Ok(left.as_float().unwrap().partial_cmp(&right.as_float().unwrap()))
+}
@@ -0,0 +1,529 @@
+use std::iter;
+use std::mem;
+use std::rc::Rc;
+
+use ast::*;
+use number::exact_bits;
+use {binary_op, bool_chain, cast, compare, is_format, unary_op};
+
+// Simplifies a resolved program before either engine runs it, without changing
+// what it does: operations on literals are worked out once, `if` branches
+// that can never run are dropped, and pairs of unary operators that cancel out
+// are removed. Anything that would fail is left alone, so it fails when it
+// runs, with the same error. So is a power or shift that would make a huge
+// number, since the code it's in might never run; nothing in a branch that's
+// dropped is worked out at all.
+//
+// It runs after the resolver, so a `let` in a branch that's dropped still
+// declares its variable.
+//
+// Loop-invariant expressions are hoisted: one in a loop that calls nothing and
+// only reads variables the loop doesn't assign gives the same value each time
+// round, since a call only assigns its own copies. It's worked out where it
+// is the first time the loop gets to it, and used again after that, so it
+// fails where it would have, takes no steps, and a loop that never gets to it
+// never works it out. A value that made a string, array, dict or big int
+// isn't kept, so the limits see the same memory used.
+
+// The most bits a power or left shift is worked out to before the script runs
+const MAX_FOLDED_BITS: u64 = 4096;
+
+/// Optimizes a script, or a line typed into the REPL.
+pub fn optimize(statements: &mut Vec<Statement>) {
+    simplify(statements);
+    hoist(statements, None, &mut 0);
+}
+
+/// Optimizes an expression typed into the REPL.
+pub fn optimize_expr(e: &mut Expr) {
+    expr(e);
+}
+
+fn simplify(statements: &mut Vec<Statement>) {
+    for s in mem::take(statements) {
+        statement(s, statements);
+    }
+}
+
+// Adds the optimized form of `s` to `out`, which may be several statements or none.
+fn statement(s: Statement, out: &mut Vec<Statement>) {
+    let mut s = match s {
+        Statement::If(if_s, elif_s, else_s) => return if_statement(if_s, elif_s, else_s, out),
+        s => s,
+    };
+
+    match s {
+        Statement::DeclareVar(_, ref mut e)
+        | Statement::DeclareConst(_, ref mut e, _)
+        | Statement::Expression(ref mut e)
+        | Statement::Return(ref mut e)
+        | Statement::Exit(ref mut e) => expr(e),
+        Statement::MutateVar(_, _, ref mut exprs) => exprs.iter_mut().for_each(expr),
+        Statement::While(ref mut condition, ref mut statements, _) => {
+            expr(condition);
+            if literal_bool(condition) != Some(false) {
+                simplify(statements);
+            }
+        },
+        Statement::Print(ref mut exprs)
+        | Statement::Println(ref mut exprs)
+        | Statement::EPrint(ref mut exprs)
+        | Statement::EPrintln(ref mut exprs) => print_args(exprs),
+        Statement::If(..) => unreachable!(),
+    }
+    out.push(s);
+}
+
+// A string literal first is a format string, so something that only folds
+// into one has to stay as it is.
+fn print_args(exprs: &mut [Expr]) {
+    let first = exprs.first().cloned();
+    let format = is_format(exprs);
+    exprs.iter_mut().for_each(expr);
+    if let Some(first) = first {
+        if !format && is_format(exprs) {
+            exprs[0] = first;
+        }
+    }
+}
+
+fn literal_bool(e: &Expr) -> Option<bool> {
+    match *e {
+        Expr::Literal(Value::Boolean(b)) => Some(b),
+        _ => None,
+    }
+}
+
+// Drops the branches that can't run, before anything in them is worked out.
+// An `elif` condition that isn't a boolean is reported with the type of the
+// `if` condition, so an `if false` that still has an `elif` after it stays,
+// with nothing in it.
+fn if_statement(mut if_s: IfStatement, elif_s: Option<Vec<IfStatement>>, mut else_s: Option<Vec<Statement>>, out: &mut Vec<Statement>) {
+    expr(&mut if_s.e);
+    if literal_bool(&if_s.e) == Some(true) {
+        simplify(&mut if_s.s);
+        return block(if_s.s, out);
+    }
+
+    let mut elifs = Vec::new();
+    for mut elif in elif_s.unwrap_or_default() {
+        expr(&mut elif.e);
+        match literal_bool(&elif.e) {
+            // Nothing after a branch that always runs ever does
+            Some(true) => {
+                else_s = Some(elif.s);
+                break;
+            },
+            Some(false) => {},
+            None => elifs.push(elif),
+        }
+    }
+    for elif in &mut elifs {
+        simplify(&mut elif.s);
+    }
+    if let Some(ref mut statements) = else_s {
+        simplify(statements);
+    }
+
+    match literal_bool(&if_s.e) {
+        Some(false) if elifs.is_empty() => return block(else_s.unwrap_or_default(), out),
+        Some(false) => if_s.s.clear(),
+        _ => simplify(&mut if_s.s),
+    }
+    let elif_s = if elifs.is_empty() { None } else { Some(elifs) };
+    out.push(Statement::If(if_s, elif_s, else_s));
+}
+
+// The statements of a branch that always runs. Blocks don't have their own
+// variables, so they can take the place of the `if`, unless they return: a
+// `return` outside a function only skips the rest of the statement it's in.
+fn block(statements: Vec<Statement>, out: &mut Vec<Statement>) {
+    if statements.iter().any(returns) {
+        let if_s = IfStatement { e: Expr::Literal(Value::Boolean(true)), s: statements };
+        out.push(Statement::If(if_s, None, None));
+    } else {
+        out.extend(statements);
+    }
+}
+
+// Whether a `return` in `s` would leave the statement it's in, rather than a loop.
+fn returns(s: &Statement) -> bool {
+    match *s {
+        Statement::Return(_) => true,
+        Statement::If(ref if_s, ref elif_s, ref else_s) => {
+            if_s.s.iter().any(returns)
+                || elif_s.iter().flatten().any(|elif| elif.s.iter().any(returns))
+                || else_s.iter().flatten().any(returns)
+        },
+        _ => false,
+    }
+}
+
+fn function(func: &mut Function) {
+    for param in &mut func.params {
+        if let Some(ref mut default) = param.default {
+            expr(default);
+        }
+    }
+    optimize(&mut func.body);
+}
+
+fn expr(e: &mut Expr) {
+    match *e {
+        Expr::Literal(_) | Expr::Reference(_) => {},
+        Expr::FuncDef(ref mut definition) => {
+            if let Value::Func(_, ref mut func) = *definition {
+                function(Rc::make_mut(func));
+            }
+        },
+        Expr::CallFunc(ref mut func, ref mut args, ref mut named) => {
+            expr(func);
+            args.iter_mut().for_each(expr);
+            for &mut (_, ref mut e) in named {
+                expr(e);
+            }
+        },
+        Expr::Array(ref mut exprs) => exprs.iter_mut().for_each(expr),
+        Expr::Dict(ref mut entries) => {
+            for &mut (_, ref mut e) in entries {
+                expr(e);
+            }
+        },
+        Expr::Typecast(ref mut left, ref mut right)
+        | Expr::Index(ref mut left, ref mut right)
+        | Expr::BinOp(_, ref mut left, ref mut right)
+        | Expr::Comparison(_, ref mut left, ref mut right)
+        | Expr::BoolChain(_, ref mut left, ref mut right) => {
+            expr(left);
+            expr(right);
+        },
+        Expr::TypeOf(ref mut e)
+        | Expr::UnOp(_, ref mut e)
+        | Expr::Length(ref mut e)
+        | Expr::ToUpper(ref mut e)
+        | Expr::ToLower(ref mut e) => expr(e),
+        Expr::Hoisted(..) => unreachable!(),
+    }
+
+    if let Some(value) = fold(e) {
+        *e = Expr::Literal(value);
+    } else if let Some(inner) = cancel(e) {
+        *e = inner;
+    }
+}
+
+// The value of an operation on literals, unless it fails.
+fn fold(e: &Expr) -> Option<Value> {
+    let (left, right) = match *e {
+        Expr::UnOp(ref op, ref e) => {
+            return match **e {
+                Expr::Literal(ref value) => unary_op(op, value).ok(),
+                _ => None,
+            };
+        },
+        Expr::Typecast(ref left, ref right)
+        | Expr::BinOp(_, ref left, ref right)
+        | Expr::Comparison(_, ref left, ref right)
+        | Expr::BoolChain(_, ref left, ref right) => match (&**left, &**right) {
+            (&Expr::Literal(ref left), &Expr::Literal(ref right)) => (left, right),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    match *e {
+        Expr::Typecast(..) => cast(left.clone(), right),
+        Expr::BinOp(op, ..) if costly(op, left, right) => return None,
+        Expr::BinOp(ref op, ..) => binary_op(op, left, right),
+        Expr::Comparison(ref op, ..) => compare(op, left, right),
+        Expr::BoolChain(ref op, ..) => bool_chain(op, left, right),
+        _ => unreachable!(),
+    }.ok()
+}
+
+// Whether `left op right` makes an int or decimal too big to work out before
+// the script runs.
+fn costly(op: Op, left: &Value, right: &Value) -> bool {
+    let bits = match exact_bits(left) {
+        Some(bits) => bits,
+        None => return false,
+    };
+    let n = match *right {
+        Value::Int(n) => n.unsigned_abs(),
+        Value::BigInt(_) => u64::MAX,
+        _ => return false,
+    };
+    match op {
+        Op::Exp => bits.saturating_sub(1).saturating_mul(n) > MAX_FOLDED_BITS,
+        Op::Shl => bits.saturating_add(n) > MAX_FOLDED_BITS,
+        _ => false,
+    }
+}
+
+// `!!x`, `--x` or `~~x`, when whatever `x` gives is certain to be something
+// the operator works on, is just `x`.
+fn cancel(e: &mut Expr) -> Option<Expr> {
+    if let Expr::UnOp(op, ref mut inner) = *e {
+        if let Expr::UnOp(inner_op, ref mut x) = **inner {
+            if op == inner_op && always_works(op, x) {
+                return Some(mem::replace(&mut **x, Expr::Literal(Value::Void)));
+            }
+        }
+    }
+    None
+}
+
+fn always_works(op: UnaryOp, e: &Expr) -> bool {
+    match (op, e) {
+        (UnaryOp::Not, &Expr::Comparison(..))
+        | (UnaryOp::Not, &Expr::BoolChain(..))
+        | (UnaryOp::Not, &Expr::UnOp(UnaryOp::Not, _))
+        | (UnaryOp::Neg, &Expr::UnOp(UnaryOp::Neg, _))
+        | (UnaryOp::Neg, &Expr::UnOp(UnaryOp::BitNot, _))
+        | (UnaryOp::Neg, &Expr::Length(_))
+        | (UnaryOp::BitNot, &Expr::UnOp(UnaryOp::BitNot, _))
+        | (UnaryOp::BitNot, &Expr::Length(_)) => true,
+        // Only `+` works on anything but numbers
+        (UnaryOp::Neg, &Expr::BinOp(op, ..)) => op != Op::Add,
+        _ => false,
+    }
+}
+
+// Wraps the loop-invariant expressions of the loops in `statements` in
+// `Expr::Hoisted`, numbering them from `next`. `assigned` is the slots the
+// loop they're in assigns, or `None` outside a loop. A loop forgets the
+// values of the loops inside it too when it starts, which they'd do anyway.
+fn hoist(statements: &mut [Statement], assigned: Option<&[Slot]>, next: &mut usize) {
+    for s in statements {
+        match *s {
+            Statement::DeclareVar(_, ref mut e)
+            | Statement::DeclareConst(_, ref mut e, _)
+            | Statement::Expression(ref mut e)
+            | Statement::Return(ref mut e)
+            | Statement::Exit(ref mut e) => hoist_exprs(iter::once(e), assigned, next),
+            Statement::MutateVar(_, _, ref mut exprs)
+            | Statement::Print(ref mut exprs)
+            | Statement::Println(ref mut exprs)
+            | Statement::EPrint(ref mut exprs)
+            | Statement::EPrintln(ref mut exprs) => hoist_exprs(exprs, assigned, next),
+            Statement::If(ref mut if_s, ref mut elif_s, ref mut else_s) => {
+                for branch in iter::once(if_s).chain(elif_s.iter_mut().flatten()) {
+                    hoist_exprs(iter::once(&mut branch.e), assigned, next);
+                    hoist(&mut branch.s, assigned, next);
+                }
+                if let Some(ref mut statements) = *else_s {
+                    hoist(statements, assigned, next);
+                }
+            },
+            Statement::While(ref mut condition, ref mut statements, ref mut hoisted) => {
+                let mut assigned = Vec::new();
+                assigned_in(statements, &mut assigned);
+                let start = *next;
+                hoist_expr(condition, &assigned, next);
+                hoist(statements, Some(&assigned), next);
+                *hoisted = start..*next;
+            },
+        }
+    }
+}
+
+fn hoist_exprs<'a, I: IntoIterator<Item = &'a mut Expr>>(exprs: I, assigned: Option<&[Slot]>, next: &mut usize) {
+    if let Some(assigned) = assigned {
+        for e in exprs {
+            hoist_expr(e, assigned, next);
+        }
+    }
+}
+
+// Hoists `e` if it's invariant, or else the parts of it that are.
+fn hoist_expr(e: &mut Expr, assigned: &[Slot], next: &mut usize) {
+    match *e {
+        // Nothing to save by keeping these
+        Expr::Literal(_) | Expr::Reference(_) => return,
+        _ if invariant(e, assigned) => {
+            let inner = mem::replace(e, Expr::Literal(Value::Void));
+            *e = Expr::Hoisted(*next, Box::new(inner));
+            *next += 1;
+            return;
+        },
+        _ => {},
+    }
+
+    match *e {
+        Expr::CallFunc(ref mut func, ref mut args, ref mut named) => {
+            hoist_expr(func, assigned, next);
+            for e in args.iter_mut().chain(named.iter_mut().map(|&mut (_, ref mut e)| e)) {
+                hoist_expr(e, assigned, next);
+            }
+        },
+        Expr::Array(ref mut exprs) => {
+            for e in exprs {
+                hoist_expr(e, assigned, next);
+            }
+        },
+        Expr::Dict(ref mut entries) => {
+            for &mut (_, ref mut e) in entries {
+                hoist_expr(e, assigned, next);
+            }
+        },
+        Expr::Typecast(ref mut left, ref mut right)
+        | Expr::Index(ref mut left, ref mut right)
+        | Expr::BinOp(_, ref mut left, ref mut right)
+        | Expr::Comparison(_, ref mut left, ref mut right)
+        | Expr::BoolChain(_, ref mut left, ref mut right) => {
+            hoist_expr(left, assigned, next);
+            hoist_expr(right, assigned, next);
+        },
+        Expr::TypeOf(ref mut e)
+        | Expr::UnOp(_, ref mut e)
+        | Expr::Length(ref mut e)
+        | Expr::ToUpper(ref mut e)
+        | Expr::ToLower(ref mut e) => hoist_expr(e, assigned, next),
+        // A function defined again is a different function, with its own closure
+        Expr::Literal(_) | Expr::Reference(_) | Expr::FuncDef(_) | Expr::Hoisted(..) => {},
+    }
+}
+
+// Whether `e` gives the same value each time round a loop that assigns the
+// variables in `assigned`.
+fn invariant(e: &Expr, assigned: &[Slot]) -> bool {
+    match *e {
+        Expr::Literal(_) => true,
+        Expr::Reference(ref var) => var.slot.map_or(false, |slot| !assigned.contains(&slot)),
+        Expr::Array(ref exprs) => exprs.iter().all(|e| invariant(e, assigned)),
+        Expr::Dict(ref entries) => entries.iter().all(|&(_, ref e)| invariant(e, assigned)),
+        Expr::Typecast(ref left, ref right)
+        | Expr::Index(ref left, ref right)
+        | Expr::BinOp(_, ref left, ref right)
+        | Expr::Comparison(_, ref left, ref right)
+        | Expr::BoolChain(_, ref left, ref right) => invariant(left, assigned) && invariant(right, assigned),
+        Expr::TypeOf(ref e)
+        | Expr::UnOp(_, ref e)
+        | Expr::Length(ref e)
+        | Expr::ToUpper(ref e)
+        | Expr::ToLower(ref e) => invariant(e, assigned),
+        Expr::FuncDef(_) | Expr::CallFunc(..) | Expr::Hoisted(..) => false,
+    }
+}
+
+// The slots of the variables `statements` declare or assign, though not in
+// the functions they define, since a call only assigns its own copies.
+fn assigned_in(statements: &[Statement], slots: &mut Vec<Slot>) {
+    for s in statements {
+        match *s {
+            Statement::DeclareVar(ref pattern, _) | Statement::DeclareConst(ref pattern, _, _) => pattern_slots(pattern, slots),
+            Statement::MutateVar(_, ref vars, _) => slots.extend(vars.iter().filter_map(|var| var.slot)),
+            Statement::If(ref if_s, ref elif_s, ref else_s) => {
+                for branch in iter::once(if_s).chain(elif_s.iter().flatten()) {
+                    assigned_in(&branch.s, slots);
+                }
+                if let Some(ref statements) = *else_s {
+                    assigned_in(statements, slots);
+                }
+            },
+            Statement::While(_, ref statements, _) => assigned_in(statements, slots),
+            _ => {},
+        }
+    }
+}
+
+fn pattern_slots(pattern: &Pattern, slots: &mut Vec<Slot>) {
+    match *pattern {
+        Pattern::Name(ref var) | Pattern::Rest(ref var) => slots.extend(var.slot),
+        Pattern::Array(ref items) => {
+            for item in items {
+                pattern_slots(item, slots);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env::Env;
+    use parser::ast;
+    use resolver::resolve;
+
+    fn optimized(source: &str) -> Vec<Statement> {
+        let mut statements = ast(source).unwrap();
+        resolve(&mut Env::default(), &mut statements).unwrap();
+        optimize(&mut statements);
+        statements
+    }
+
+    fn check(source: &str, expected: &str) {
+        assert_eq!(optimized(source), ast(expected).unwrap());
+    }
+
+    fn hoisted(i: usize, source: &str) -> Expr {
+        match ast(&format!("{};", source)).unwrap().pop() {
+            Some(Statement::Expression(e)) => Expr::Hoisted(i, Box::new(e)),
+            s => panic!("not an expression: {:?}", s),
+        }
+    }
+
+    #[test]
+    fn literals_are_folded() {
+        check(
+            "let day = 60 * 60 * 24; let s = \"a\" + \"b\"; let t = 3 < 4 || false; let n = \"7\" as int;",
+            "let day = 86400; let s = \"ab\"; let t = true; let n = 7;",
+        );
+        // Anything that fails is left to fail when it runs
+        check("let a = 7 % 0; let b = \"x\" as int; let c = 1 < \"a\";", "let a = 7 % 0; let b = \"x\" as int; let c = 1 < \"a\";");
+        check("println \"{\" + \"}\", 1; println \"a\" + \"b\", 1;", "println \"{\" + \"}\", 1; println \"ab\", 1;");
+    }
+
+    #[test]
+    fn dead_branches_are_dropped() {
+        check(
+            "let x = 1; if false { print 1; } elif x > 0 { print 2; } elif false { print 3; } elif true { print 4; } else { print 5; }",
+            "let x = 1; if false { } elif x > 0 { print 2; } else { print 4; }",
+        );
+        check("if 1 > 2 { print 1; } else { print 2; } if true { print 3; } print 4;", "print 2; print 3; print 4;");
+        check("if true { return 1; } if 1 { print 2; }", "if true { return 1; } if 1 { print 2; }");
+        // Nothing in them is worked out, so this doesn't take long
+        check("print 1; if false { print 7 ** 3000000; } elif true { } else { print 7 ** 3000000; }", "print 1;");
+    }
+
+    #[test]
+    fn huge_ints_are_left_until_they_are_needed() {
+        check(
+            "let a = 7 ** 3000000; let b = 1 << 100000; let c = 1.5d ** 10000; let d = 2 ** 100; let e = 1 ** 3000000;",
+            "let a = 7 ** 3000000; let b = 1 << 100000; let c = 1.5d ** 10000; let d = 1267650600228229401496703205376; let e = 1;",
+        );
+    }
+
+    #[test]
+    fn loop_invariants_are_hoisted() {
+        let statements = optimized("let f = func(x: int) { return x; }; let n = 2; let i = 0; while i < n * 3 { i += n + 1; print i * -n, f(n * 2); }");
+        let i = || Box::new(Expr::Reference(Var::new("i".to_string())));
+        let expected = Statement::While(
+            Expr::Comparison(CompOp::Lt, i(), Box::new(hoisted(0, "n * 3"))),
+            vec![
+                Statement::MutateVar(AssignOp::AddEq, vec![Var::new("i".to_string())], vec![hoisted(1, "n + 1")]),
+                Statement::Print(vec![
+                    Expr::BinOp(Op::Mul, i(), Box::new(hoisted(2, "-n"))),
+                    Expr::CallFunc(Box::new(Expr::Reference(Var::new("f".to_string()))), vec![hoisted(3, "n * 2")], Vec::new()),
+                ]),
+            ],
+            0..4,
+        );
+        assert_eq!(statements[3], expected);
+
+        // Nothing the loop assigns, even in a loop inside it, and no calls
+        check(
+            "let n = 1; while n < 9 { let m = n * 2; while true { n = m + n; } print f(1) + 1; } let f = func() { };",
+            "let n = 1; while n < 9 { let m = n * 2; while true { n = m + n; } print f(1) + 1; } let f = func() { };",
+        );
+    }
+
+    #[test]
+    fn unary_operators_cancel_out() {
+        check(
+            "let x = 1; let a = !!(x < 2); let b = !!!(x < 2); let c = -(-(x * 2)); let d = ~~length([x]); let e = !!x; let f = --x;",
+            "let x = 1; let a = x < 2; let b = !(x < 2); let c = x * 2; let d = length([x]); let e = !!x; let f = --x;",
+        );
+        check("let a = !!true; let b = --5; let c = ~~7;", "let a = true; let b = 5; let c = 7;");
+    }
+}
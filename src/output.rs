@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::io::{self, BufWriter, ErrorKind, Write};
+#[cfg(test)]
+use std::rc::Rc;
+
+// Where `print` and `eprint` send their text. Standard output is buffered and
+// flushed when the script ends; standard error isn't, but writing to it
+// flushes standard output first so the two stay in order on a terminal.
+// A host can swap in any other `Write`, for example to capture output in tests.
+//
+// If nobody is reading any more (as in `toylang script | head`), there's no
+// point in carrying on, so the script stops with an error. It isn't one the
+// script made, though: the interpreter exits quietly, like other command line
+// tools, and a host can tell it apart with `closed()`.
+
+struct Streams {
+    out: Box<dyn Write>,
+    err: Box<dyn Write>,
+    closed: bool, // A write failed because the other end has gone away
+}
+
+thread_local! {
+    static STREAMS: RefCell<Streams> = RefCell::new(Streams {
+        out: Box::new(BufWriter::new(io::stdout())),
+        err: Box::new(io::stderr()),
+        closed: false,
+    });
+}
+
+/// Replaces the output streams, returning the old ones after flushing them.
+#[allow(dead_code)] // Only used by hosts and tests
+pub fn set_streams(out: Box<dyn Write>, err: Box<dyn Write>) -> (Box<dyn Write>, Box<dyn Write>) {
+    flush();
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let old_out = ::std::mem::replace(&mut streams.out, out);
+        let old_err = ::std::mem::replace(&mut streams.err, err);
+        streams.closed = false;
+        (old_out, old_err)
+    })
+}
+
+/// Whether the script was stopped because whatever was reading its output
+/// went away, rather than by an error of its own.
+pub fn closed() -> bool {
+    STREAMS.with(|streams| streams.borrow().closed)
+}
+
+impl Streams {
+    fn check(&mut self, statement: &str, result: io::Result<()>) -> Result<(), String> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if e.kind() == ErrorKind::BrokenPipe {
+                    self.closed = true;
+                }
+                Err(format!("{}: {}", statement, e))
+            },
+        }
+    }
+}
+
+pub fn write_out(statement: &str, text: &str) -> Result<(), String> {
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let result = streams.out.write_all(text.as_bytes());
+        streams.check(statement, result)
+    })
+}
+
+pub fn write_err(statement: &str, text: &str) -> Result<(), String> {
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let result = streams.out.flush();
+        streams.check(statement, result)?;
+        let result = streams.err.write_all(text.as_bytes()).and_then(|_| streams.err.flush());
+        streams.check(statement, result)
+    })
+}
+
+/// Writes out anything still buffered. Called before the interpreter exits
+/// and whenever the REPL waits for input.
+pub fn flush() {
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let _ = streams.out.flush();
+        let _ = streams.err.flush();
+    })
+}
+
+/// Keeps everything written to it, for tests to look at.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Capture {
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(ErrorKind::BrokenPipe, "broken pipe"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn captured_streams() {
+        let out = Capture::default();
+        let err = Capture::default();
+        set_streams(Box::new(out.clone()), Box::new(err.clone()));
+
+        write_out("print", "hello ").unwrap();
+        write_out("println", "world\n").unwrap();
+        write_err("eprintln", "oops\n").unwrap();
+
+        assert_eq!(out.text(), "hello world\n");
+        assert_eq!(err.text(), "oops\n");
+    }
+
+    #[test]
+    fn closed_streams_stop_the_script() {
+        let err = Capture::default();
+        set_streams(Box::new(Closed), Box::new(err.clone()));
+        assert_eq!(write_out("print", "hello"), Err("print: broken pipe".to_string()));
+        assert!(closed());
+
+        set_streams(Box::new(Capture::default()), Box::new(err));
+        assert!(!closed());
+    }
+}
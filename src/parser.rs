@@ -1,8 +1,17 @@
 include!(concat!(env!("OUT_DIR"), "/grammar.rs"));
 
+// The value of a hex, octal or binary literal; `digits` may contain underscores.
+fn radix_literal(sign: &str, digits: &str, radix: u32) -> Value {
+    let n = ::bigint::BigInt::parse_radix(&digits.replace("_", ""), radix).unwrap();
+    ::number::normalize(if sign == "-" { n.neg() } else { n })
+}
+
 #[cfg(test)]
 mod tests {
     use parser::*;
+    use bigint::BigInt;
+    use decimal::Decimal;
+    use std::rc::Rc;
 
     #[test]
     fn assign_int() {
@@ -16,7 +25,124 @@ mod tests {
     fn eval_int() {
         assert_eq!(
             expression("1").unwrap(),
-            Expr::Literal(Value::Num(1.0))
+            Expr::Literal(Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn eval_big_numbers() {
+        assert_eq!(
+            expression("-9223372036854775808").unwrap(),
+            Expr::Literal(Value::Int(i64::MIN))
+        );
+        assert_eq!(
+            expression("9223372036854775808").unwrap(),
+            Expr::Literal(Value::BigInt(BigInt::parse("9223372036854775808").unwrap()))
+        );
+        assert_eq!(
+            expression("-1.10d").unwrap(),
+            Expr::Literal(Value::Decimal(Decimal::parse("-1.10").unwrap()))
+        );
+        assert!(expression("2d").is_ok());
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(expression("0xff").unwrap(), Expr::Literal(Value::Int(255)));
+        assert_eq!(expression("-0b1010_1010").unwrap(), Expr::Literal(Value::Int(-170)));
+        assert_eq!(expression("0o17").unwrap(), Expr::Literal(Value::Int(15)));
+        assert_eq!(expression("1e6").unwrap(), Expr::Literal(Value::Num(1e6)));
+        assert_eq!(expression("2.5E-3").unwrap(), Expr::Literal(Value::Num(2.5e-3)));
+    }
+
+    #[test]
+    fn unary_and_bitwise_operators() {
+        assert!(expression("-x").is_ok());
+        assert!(expression("-(a + b) * ~c").is_ok());
+        assert!(expression("a & b | c ^ d << 2 >> 1").is_ok());
+        assert!(expression("a // b").is_ok());
+        assert!(statement("a //= 2;").is_ok());
+        assert!(statement("a <<= b;").is_ok());
+        assert!(statement("let a = 1; // comment").is_ok());
+        // `//` after an operand on the same line divides; anywhere else it starts a comment
+        assert_eq!(ast("println 5 // 2\n+ 1;"), ast("println (5 // 2) + 1;"));
+        assert_eq!(ast("println 5\n// 2\n+ 1;"), ast("println 5 + 1;"));
+        assert_eq!(ast("let b = a\n// note\n;"), ast("let b = a;"));
+        assert_eq!(
+            expression("1 | 2 == 3").unwrap(),
+            Expr::Comparison(
+                CompOp::Equal,
+                Box::new(Expr::BinOp(Op::BitOr, Box::new(Expr::Literal(Value::Int(1))), Box::new(Expr::Literal(Value::Int(2))))),
+                Box::new(Expr::Literal(Value::Int(3))),
+            )
+        );
+    }
+
+    #[test]
+    fn destructuring() {
+        assert_eq!(
+            statement("let [q, [r], ...rest] = x;").unwrap(),
+            Statement::DeclareVar(
+                Pattern::Array(vec![
+                    Pattern::Name(Var::new("q".to_string())),
+                    Pattern::Array(vec![Pattern::Name(Var::new("r".to_string()))]),
+                    Pattern::Rest(Var::new("rest".to_string())),
+                ]),
+                Expr::Reference(Var::new("x".to_string())),
+            )
+        );
+        assert_eq!(
+            statement("a, b = b, a;").unwrap(),
+            Statement::MutateVar(
+                AssignOp::Equals,
+                vec![Var::new("a".to_string()), Var::new("b".to_string())],
+                vec![Expr::Reference(Var::new("b".to_string())), Expr::Reference(Var::new("a".to_string()))],
+            )
+        );
+        assert!(statement("let ...rest = x;").is_err());
+        assert!(statement("f(a, b);").is_ok());
+    }
+
+    #[test]
+    fn parameters_and_named_arguments() {
+        assert_eq!(
+            expression("func(a: num, sep: string = \", \", ...rest: array) { }").unwrap(),
+            Expr::FuncDef(Value::Func(None, Rc::new(Function::new(vec![
+                Param { name: "a".to_string(), t: Type::Num, default: None, variadic: false },
+                Param { name: "sep".to_string(), t: Type::String, default: Some(Expr::Literal(Value::string(", ".to_string()))), variadic: false },
+                Param { name: "rest".to_string(), t: Type::Array, default: None, variadic: true },
+            ], vec![]))))
+        );
+        assert_eq!(
+            expression("f(x, sep: \"|\")").unwrap(),
+            Expr::CallFunc(
+                Box::new(Expr::Reference(Var::new("f".to_string()))),
+                vec![Expr::Reference(Var::new("x".to_string()))],
+                vec![("sep".to_string(), Expr::Literal(Value::string("|".to_string())))],
+            )
+        );
+        assert!(expression("f(sep: 1)(2)").is_ok());
+        assert!(expression("f(sep: 1, x)").is_err());
+        assert_eq!(
+            type_ident("func(num, string?, ...array)").unwrap(),
+            Type::Func(vec![
+                ParamType::Required(Type::Num),
+                ParamType::Optional(Type::String),
+                ParamType::Variadic(Type::Array),
+            ])
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(
+            ast("let a = 1;\n\nconst LIMIT = 10;").unwrap()[1],
+            Statement::DeclareConst(Pattern::Name(Var::new("LIMIT".to_string())), Expr::Literal(Value::Int(10)), 3)
+        );
+        assert!(statement("const [a, b] = pair;").is_ok());
+        assert_eq!(
+            statement("constant = 1;").unwrap(),
+            Statement::MutateVar(AssignOp::Equals, vec![Var::new("constant".to_string())], vec![Expr::Literal(Value::Int(1))])
         );
     }
 
@@ -46,6 +172,15 @@ mod tests {
         assert!(expression("[bar][2 + baz]").is_ok());
     }
 
+    #[test]
+    fn dicts() {
+        assert!(expression(r#"{}"#).is_ok());
+        assert!(expression(r#"{"a": 1, "b": [foo, bar]}"#).is_ok());
+        assert!(expression(r#"{"a": {"b": 2}}["a"]["b"]"#).is_ok());
+        assert_eq!(type_ident("dict").unwrap(), Type::Dict);
+        assert_eq!(expression("dictionary").unwrap(), Expr::Reference(Var::new("dictionary".to_string())));
+    }
+
     #[test]
     fn eval_bools() {
         assert_eq!(
@@ -65,6 +200,8 @@ mod tests {
         assert!(expression("5 + 5 as string").is_ok());
         assert!(expression("5 as string + 5").is_ok());
         assert!(expression("ARGV[1] as num").is_ok());
+        assert!(expression("2.5 as int").is_ok());
+        assert_eq!(expression("interval").unwrap(), Expr::Reference(Var::new("interval".to_string())));
         assert!(expression("![true][0] as string").is_ok());
     }
 
@@ -0,0 +1,288 @@
+use std::iter;
+use std::rc::Rc;
+
+use ast::*;
+use builtins;
+use env::Env;
+
+// Works out which slot every variable refers to before anything runs, so
+// both engines can index straight into it, and so a name that isn't declared
+// anywhere is reported once, up front.
+//
+// A function can see its caller's variables, so any name it uses might be one
+// of them. Each gets a local slot, which the call fills in the first time it's
+// used (see `env.rs`), and every name that's declared anywhere gets a global
+// slot, which is where the top level finds it. Blocks don't have their own
+// variables.
+
+struct Resolver<'a> {
+    env: &'a mut Env, // Where the globals get their slots
+    functions: Vec<Vec<String>>, // The locals of each function being resolved, innermost last
+}
+
+/// Resolves a script, or a line typed into the REPL.
+pub fn resolve(env: &mut Env, statements: &mut [Statement]) -> Result<(), String> {
+    let mut resolver = Resolver { env: env, functions: Vec::new() };
+    resolver.declare_all(statements)?;
+    resolver.statements(statements)
+}
+
+/// Resolves an expression typed into the REPL.
+pub fn resolve_expr(env: &mut Env, expr: &mut Expr) -> Result<(), String> {
+    let mut resolver = Resolver { env: env, functions: Vec::new() };
+    resolver.declare_in(expr)?;
+    resolver.expr(expr)
+}
+
+impl<'a> Resolver<'a> {
+    // Gives everything bound by `let`, `const` or a parameter in
+    // `statements` a global slot, including inside the functions they define.
+    fn declare_all(&mut self, statements: &[Statement]) -> Result<(), String> {
+        for s in statements {
+            match *s {
+                Statement::DeclareVar(ref pattern, ref expr) | Statement::DeclareConst(ref pattern, ref expr, _) => {
+                    self.declare_pattern(pattern)?;
+                    self.declare_in(expr)?;
+                },
+                Statement::MutateVar(_, _, ref exprs)
+                | Statement::Print(ref exprs)
+                | Statement::Println(ref exprs)
+                | Statement::EPrint(ref exprs)
+                | Statement::EPrintln(ref exprs) => {
+                    for expr in exprs {
+                        self.declare_in(expr)?;
+                    }
+                },
+                Statement::Expression(ref expr) | Statement::Return(ref expr) | Statement::Exit(ref expr) => {
+                    self.declare_in(expr)?;
+                },
+                Statement::If(ref if_s, ref elif_s, ref else_s) => {
+                    for branch in iter::once(if_s).chain(elif_s.iter().flatten()) {
+                        self.declare_in(&branch.e)?;
+                        self.declare_all(&branch.s)?;
+                    }
+                    if let Some(ref statements) = *else_s {
+                        self.declare_all(statements)?;
+                    }
+                },
+                Statement::While(ref condition, ref statements, _) => {
+                    self.declare_in(condition)?;
+                    self.declare_all(statements)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Declares the parameters and variables of the functions defined in `expr`.
+    fn declare_in(&mut self, expr: &Expr) -> Result<(), String> {
+        match *expr {
+            Expr::Literal(_) | Expr::Reference(_) => {},
+            Expr::FuncDef(ref definition) => {
+                if let Value::Func(_, ref func) = *definition {
+                    for param in &func.params {
+                        self.env.add_global(&param.name);
+                        if let Some(ref default) = param.default {
+                            self.declare_in(default)?;
+                        }
+                    }
+                    self.declare_all(&func.body)?;
+                }
+            },
+            Expr::CallFunc(ref func, ref args, ref named) => {
+                self.declare_in(func)?;
+                for expr in args.iter().chain(named.iter().map(|&(_, ref e)| e)) {
+                    self.declare_in(expr)?;
+                }
+            },
+            Expr::Array(ref exprs) => {
+                for expr in exprs {
+                    self.declare_in(expr)?;
+                }
+            },
+            Expr::Dict(ref entries) => {
+                for &(_, ref expr) in entries {
+                    self.declare_in(expr)?;
+                }
+            },
+            Expr::Typecast(ref left, ref right)
+            | Expr::Index(ref left, ref right)
+            | Expr::BinOp(_, ref left, ref right)
+            | Expr::Comparison(_, ref left, ref right)
+            | Expr::BoolChain(_, ref left, ref right) => {
+                self.declare_in(left)?;
+                self.declare_in(right)?;
+            },
+            Expr::TypeOf(ref expr)
+            | Expr::UnOp(_, ref expr)
+            | Expr::Length(ref expr)
+            | Expr::ToUpper(ref expr)
+            | Expr::ToLower(ref expr)
+            | Expr::Hoisted(_, ref expr) => self.declare_in(expr)?,
+        }
+        Ok(())
+    }
+
+    fn declare_pattern(&mut self, pattern: &Pattern) -> Result<(), String> {
+        match *pattern {
+            Pattern::Name(ref var) | Pattern::Rest(ref var) => {
+                self.env.add_global(&Ident::new(var.name.clone())?.0);
+            },
+            Pattern::Array(ref items) => {
+                for item in items {
+                    self.declare_pattern(item)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // The slot for `name` in the function being resolved, or the global one at the top level.
+    fn slot(&mut self, name: &str) -> Slot {
+        match self.functions.last_mut() {
+            Some(locals) => match locals.iter().position(|local| local == name) {
+                Some(i) => Slot::Local(i),
+                None => {
+                    locals.push(name.to_string());
+                    Slot::Local(locals.len() - 1)
+                },
+            },
+            None => Slot::Global(self.env.add_global(name)),
+        }
+    }
+
+    // The slot for a name that's used, if it's declared anywhere.
+    fn lookup(&mut self, name: &str) -> Option<Slot> {
+        self.env.global_slot(name).map(|_| self.slot(name))
+    }
+
+    fn pattern(&mut self, pattern: &mut Pattern) {
+        match *pattern {
+            Pattern::Name(ref mut var) | Pattern::Rest(ref mut var) => var.slot = Some(self.slot(&var.name)),
+            Pattern::Array(ref mut items) => {
+                for item in items {
+                    self.pattern(item);
+                }
+            },
+        }
+    }
+
+    fn statements(&mut self, statements: &mut [Statement]) -> Result<(), String> {
+        for s in statements {
+            self.statement(s)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &mut Statement) -> Result<(), String> {
+        match *statement {
+            Statement::DeclareVar(ref mut pattern, ref mut expr) | Statement::DeclareConst(ref mut pattern, ref mut expr, _) => {
+                if let (&mut Pattern::Name(ref var), &mut Expr::FuncDef(Value::Func(_, ref mut func))) = (&mut *pattern, &mut *expr) {
+                    Rc::make_mut(func).name = Some(var.name.clone());
+                }
+                self.expr(expr)?;
+                self.pattern(pattern);
+            },
+            Statement::MutateVar(_, ref mut vars, ref mut exprs) => {
+                for var in vars {
+                    Ident::new(var.name.clone())?;
+                    var.slot = Some(self.lookup(&var.name).ok_or_else(|| format!("undeclared variable: {}", var.name))?);
+                }
+                self.exprs(exprs)?;
+            },
+            Statement::Expression(ref mut expr) | Statement::Return(ref mut expr) | Statement::Exit(ref mut expr) => {
+                self.expr(expr)?;
+            },
+            Statement::If(ref mut if_s, ref mut elif_s, ref mut else_s) => {
+                self.expr(&mut if_s.e)?;
+                self.statements(&mut if_s.s)?;
+                for elif in elif_s.iter_mut().flatten() {
+                    self.expr(&mut elif.e)?;
+                    self.statements(&mut elif.s)?;
+                }
+                if let Some(ref mut statements) = *else_s {
+                    self.statements(statements)?;
+                }
+            },
+            Statement::While(ref mut condition, ref mut statements, _) => {
+                self.expr(condition)?;
+                self.statements(statements)?;
+            },
+            Statement::Print(ref mut exprs)
+            | Statement::Println(ref mut exprs)
+            | Statement::EPrint(ref mut exprs)
+            | Statement::EPrintln(ref mut exprs) => self.exprs(exprs)?,
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, func: &mut Function) -> Result<(), String> {
+        self.functions.push(func.params.iter().map(|p| p.name.clone()).collect());
+        // Defaults are worked out in the call, so they can use the parameters before them
+        for param in &mut func.params {
+            if let Some(ref mut default) = param.default {
+                self.expr(default)?;
+            }
+        }
+        self.statements(&mut func.body)?;
+        func.locals = self.functions.pop().unwrap();
+        func.captures = func.locals.iter().map(|name| self.slot(name)).collect();
+        Ok(())
+    }
+
+    fn exprs(&mut self, exprs: &mut [Expr]) -> Result<(), String> {
+        for expr in exprs {
+            self.expr(expr)?;
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> Result<(), String> {
+        match *expr {
+            Expr::Literal(_) => {},
+            Expr::FuncDef(ref mut definition) => {
+                if let Value::Func(_, ref mut func) = *definition {
+                    self.function(Rc::make_mut(func))?;
+                }
+            },
+            Expr::Reference(ref mut var) => {
+                Ident::new(var.name.clone())?;
+                var.slot = Some(self.lookup(&var.name).ok_or_else(|| format!("Undefined variable: {}", var.name))?);
+            },
+            Expr::CallFunc(ref mut func, ref mut args, ref mut named) => {
+                match **func {
+                    // A builtin is called unless a variable with the same name is defined
+                    Expr::Reference(ref mut var) if builtins::lookup(&var.name).is_some() => {
+                        var.slot = self.lookup(&var.name);
+                    },
+                    _ => self.expr(func)?,
+                }
+                self.exprs(args)?;
+                for &mut (_, ref mut expr) in named {
+                    self.expr(expr)?;
+                }
+            },
+            Expr::Array(ref mut exprs) => self.exprs(exprs)?,
+            Expr::Dict(ref mut entries) => {
+                for &mut (_, ref mut expr) in entries {
+                    self.expr(expr)?;
+                }
+            },
+            Expr::Typecast(ref mut left, ref mut right)
+            | Expr::Index(ref mut left, ref mut right)
+            | Expr::BinOp(_, ref mut left, ref mut right)
+            | Expr::Comparison(_, ref mut left, ref mut right)
+            | Expr::BoolChain(_, ref mut left, ref mut right) => {
+                self.expr(left)?;
+                self.expr(right)?;
+            },
+            Expr::TypeOf(ref mut expr)
+            | Expr::UnOp(_, ref mut expr)
+            | Expr::Length(ref mut expr)
+            | Expr::ToUpper(ref mut expr)
+            | Expr::ToLower(ref mut expr)
+            | Expr::Hoisted(_, ref mut expr) => self.expr(expr)?,
+        }
+        Ok(())
+    }
+}
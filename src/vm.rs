@@ -0,0 +1,479 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+
+use ast::*;
+use builtins;
+use compiler::{self, Chunk, Instr};
+use env::Env;
+use limits;
+use {assign, assigned_value, binary_op, bool_chain, call_env, cast, check_assign_targets, compare, declare, exit_with};
+use {format_values, index_value, length, make_func, print, to_lower, to_upper, unary_op};
+
+// A stack machine that runs the bytecode from `compiler.rs`. Every frame has
+// its own variables, set up exactly as the tree walker sets them up, and the
+// operations themselves are shared with it, so the two engines behave the same.
+
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    env: Env,
+    base: usize, // Where this frame's values start on the stack
+}
+
+struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    made: u64, // What `limits::made` was when the hoisted expression being worked out started
+}
+
+/// Runs a script, or a line typed into the REPL, with the variables in `global_vars`.
+pub fn run(global_vars: &mut Env, statements: &[Statement]) -> Result<(), String> {
+    let chunk = Rc::new(compiler::compile_program(statements));
+    Vm::new().run_chunk(global_vars, chunk).map(|_| ())
+}
+
+pub fn eval(global_vars: &Env, expr: &Expr) -> Result<Value, String> {
+    let chunk = Rc::new(compiler::compile_expr(expr));
+    Vm::new().run_chunk(&mut global_vars.clone(), chunk)
+}
+
+// The code for a function and the variables it starts with.
+fn prepare_call(caller: &Env, func: Value, args: Vec<Value>, named: Vec<(String, Value)>) -> Result<(Rc<Chunk>, Env), String> {
+    if let Value::Func(closure, func) = func {
+        let env = call_env(caller, closure, &func, args, named, &mut eval)?;
+        let chunk = func.code.get_or_init(|| Rc::new(compiler::compile_function(&func.body))).clone();
+        Ok((chunk, env))
+    } else {
+        Err(format!("cannot call {} as function", func.get_type()))
+    }
+}
+
+impl Vm {
+    fn new() -> Vm {
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            made: 0,
+        }
+    }
+
+    // The variables are moved into the frame while it runs and moved back
+    // afterwards, even after an error, since the tree walker keeps whatever
+    // was assigned before one.
+    fn run_chunk(&mut self, global_vars: &mut Env, chunk: Rc<Chunk>) -> Result<Value, String> {
+        let depth = self.frames.len();
+        self.frames.push(Frame {
+            chunk: chunk,
+            ip: 0,
+            env: mem::take(global_vars),
+            base: self.stack.len(),
+        });
+
+        let result = self.execute(depth);
+
+        self.frames.truncate(depth + 1);
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        *global_vars = frame.env;
+        result
+    }
+
+    /// Calls a function for a builtin, like the callback passed to `map`, on
+    /// top of the frames that are already running.
+    fn call_value(&mut self, caller: &Env, func: Value, args: Vec<Value>) -> Result<Value, String> {
+        let (chunk, mut env) = prepare_call(caller, func, args, Vec::new())?;
+        self.run_chunk(&mut env, chunk)
+    }
+
+    fn env(&self) -> &Env {
+        &self.frames.last().unwrap().env
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn pop_n(&mut self, n: usize) -> Vec<Value> {
+        let at = self.stack.len() - n;
+        self.stack.split_off(at)
+    }
+
+    // Runs until the frame at `depth` returns. The running frame's code and
+    // position are kept in locals, and only written back to it for a call.
+    fn execute(&mut self, depth: usize) -> Result<Value, String> {
+        let mut chunk = self.frames.last().unwrap().chunk.clone();
+        let mut ip = 0;
+        loop {
+            let instr = chunk.code[ip];
+            ip += 1;
+
+            match instr {
+                Instr::Step => limits::step()?,
+                Instr::Const(i) => self.stack.push(chunk.consts[i].clone()),
+                Instr::Load(slot, i) => {
+                    let value = self.env().get_slot(slot).ok_or_else(|| format!("Undefined variable: {}", chunk.vars[i].name))?;
+                    self.stack.push(value);
+                },
+                Instr::Fail(i) => return Err(chunk.errors[i].clone()),
+                Instr::MakeFunc(i) => {
+                    let func = make_func(self.env(), &chunk.consts[i])?;
+                    self.stack.push(func);
+                },
+                Instr::Cast => {
+                    let new_type = self.pop();
+                    let value = self.pop();
+                    self.stack.push(cast(value, &new_type)?);
+                },
+                Instr::TypeOf => {
+                    let value = self.pop();
+                    self.stack.push(Value::Type(value.get_type()));
+                },
+                Instr::Array(n) => {
+                    let array = self.pop_n(n);
+                    self.stack.push(Value::array(array));
+                },
+                Instr::Dict(keys) => {
+                    let keys = &chunk.name_lists[keys];
+                    let values = self.pop_n(keys.len());
+                    let dict = keys.iter().cloned().zip(values).collect::<BTreeMap<_, _>>();
+                    self.stack.push(Value::dict(dict));
+                },
+                Instr::Index => {
+                    let index = self.pop();
+                    let var = self.pop();
+                    self.stack.push(index_value(&var, &index)?);
+                },
+                // The result replaces the left operand where it is
+                Instr::BinOp(op) => {
+                    let right = self.pop();
+                    let left = self.stack.last_mut().unwrap();
+                    *left = binary_op(&op, left, &right)?;
+                },
+                Instr::Compare(op) => {
+                    let right = self.pop();
+                    let left = self.stack.last_mut().unwrap();
+                    *left = compare(&op, left, &right)?;
+                },
+                Instr::BoolChain(op) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(bool_chain(&op, &left, &right)?);
+                },
+                Instr::Unary(op) => {
+                    let value = self.pop();
+                    self.stack.push(unary_op(&op, &value)?);
+                },
+                Instr::Length => {
+                    let value = self.pop();
+                    self.stack.push(length(&value)?);
+                },
+                Instr::ToUpper => {
+                    let value = self.pop();
+                    self.stack.push(to_upper(&value)?);
+                },
+                Instr::ToLower => {
+                    let value = self.pop();
+                    self.stack.push(to_lower(&value)?);
+                },
+                Instr::JumpIfDefined(var, target) => {
+                    if self.env().is_defined(&chunk.vars[var]) {
+                        ip = target;
+                    }
+                },
+                Instr::Hoisted(i, target) => match self.env().hoisted(i) {
+                    Some(value) => {
+                        self.stack.push(value);
+                        ip = target;
+                    },
+                    None => self.made = limits::made(),
+                },
+                Instr::Hoist(i) => {
+                    if limits::made() == self.made {
+                        let value = self.stack.last().unwrap().clone();
+                        self.env().hoist(i, value);
+                    }
+                },
+                Instr::Forget(start, end) => self.env().forget_hoisted(start..end),
+                Instr::CallBuiltin(b, n) => {
+                    let args = self.pop_n(n);
+                    let env = self.env().clone();
+                    let (builtin, ref arg_names) = chunk.builtins[b];
+                    let result = {
+                        let vm = &mut *self;
+                        let mut caller = |func, args| vm.call_value(&env, func, args);
+                        builtin(&mut builtins::Context::new(&mut caller, arg_names, &env), args)?
+                    };
+                    self.stack.push(result);
+                },
+                Instr::Call(n, names) => {
+                    let names = &chunk.name_lists[names];
+                    let named_values = self.pop_n(names.len());
+                    let named = names.iter().cloned().zip(named_values).collect();
+                    let args = self.pop_n(n);
+                    let func = self.pop();
+
+                    let (code, env) = prepare_call(self.env(), func, args, named)?;
+                    self.frames.last_mut().unwrap().ip = ip;
+                    self.frames.push(Frame {
+                        chunk: code.clone(),
+                        ip: 0,
+                        env: env,
+                        base: self.stack.len(),
+                    });
+                    chunk = code;
+                    ip = 0;
+                },
+                Instr::Pop => {
+                    self.pop();
+                },
+                Instr::Declare(p) | Instr::DeclareConst(p, _) => {
+                    let value = self.pop();
+                    let line = if let Instr::DeclareConst(_, line) = instr { Some(line) } else { None };
+                    let env = &mut self.frames.last_mut().unwrap().env;
+                    declare(env, &chunk.patterns[p], value, line)?;
+                },
+                Instr::CheckAssign(names) => check_assign_targets(self.env(), &chunk.var_lists[names])?,
+                Instr::Assign(op, names, n) => {
+                    let vars = &chunk.var_lists[names];
+                    if n == 1 && vars.len() == 1 {
+                        // The usual `x = value;`, without gathering the values into a vec
+                        let rhs = self.stack.pop().unwrap();
+                        let env = &mut self.frames.last_mut().unwrap().env;
+                        env.update(&vars[0], |value| assigned_value(&op, value, rhs))?;
+                    } else {
+                        let values = self.pop_n(n);
+                        assign(&mut self.frames.last_mut().unwrap().env, &op, vars, values)?;
+                    }
+                },
+                Instr::Jump(target) => ip = target,
+                Instr::JumpIfFalse(target) | Instr::JumpIfFalseElif(target) => {
+                    match self.pop() {
+                        Value::Boolean(true) => {},
+                        Value::Boolean(false) => ip = target,
+                        // The tree walker names the type of the `if` condition even for an `elif`,
+                        // and that was a bool or it wouldn't have got this far
+                        _ if instr != Instr::JumpIfFalse(target) => return Err(format!("expected boolean, found {}", Type::Boolean)),
+                        other => return Err(format!("expected boolean, found {}", other.get_type())),
+                    }
+                },
+                Instr::Print(statement, n, format) => {
+                    let values = self.pop_n(n);
+                    let text = format_values(self.env(), statement, &values, format)?;
+                    print(statement, text)?;
+                },
+                Instr::Exit => {
+                    let status = self.pop();
+                    exit_with(&status);
+                },
+                Instr::Return => {
+                    let value = self.pop();
+                    if self.frames.len() - 1 == depth {
+                        return Ok(value);
+                    }
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    self.stack.push(value);
+
+                    let caller = self.frames.last().unwrap();
+                    chunk = caller.chunk.clone();
+                    ip = caller.ip;
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::Duration;
+
+    use env::{Env, DEFAULT_MAX_DEPTH};
+    use limits::{self, Exceeded, Limits};
+    use output::{self, Capture};
+    use {global_vars, parser, run, Engine};
+
+    // What a script prints, followed by its error if it fails.
+    fn run_with(engine: Engine, source: &str) -> String {
+        run_in(engine, &mut global_vars(DEFAULT_MAX_DEPTH), source)
+    }
+
+    fn run_in(engine: Engine, global_vars: &mut Env, source: &str) -> String {
+        let out = Capture::default();
+        let (old_out, old_err) = output::set_streams(Box::new(out.clone()), Box::new(out.clone()));
+        let result = run(engine, global_vars, parser::ast(source).unwrap());
+        output::set_streams(old_out, old_err);
+
+        let text = out.text();
+        match result {
+            Ok(()) => text,
+            Err(e) => format!("{}Error: {}", text, e),
+        }
+    }
+
+    // What a script prints with `limits`, and which of them stopped it.
+    fn run_limited(engine: Engine, limits: &Limits, source: &str) -> (String, Option<Exceeded>) {
+        limits::set(limits.clone());
+        let text = run_with(engine, source);
+        let exceeded = limits::exceeded();
+        limits::set(Limits::default());
+        (text, exceeded)
+    }
+
+    fn check(source: &str, expected: &str) {
+        assert_eq!(run_with(Engine::Tree, source), expected);
+        assert_eq!(run_with(Engine::Bytecode, source), expected);
+    }
+
+    #[test]
+    fn engines_agree() {
+        check("let i = 0; while i < 3 { i += 1; print i; }", "123");
+        check(
+            "let fib = func(n: num) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }; println fib(15);",
+            "610\n",
+        );
+        check(
+            "let make = func(n: num) { return func(m: num) { return n + m; }; }; println map([1, 2], make(5));",
+            "[6, 7]\n",
+        );
+        check("let f = func(a: num, b: num = a * 2, ...c: array) { return [a, b, c]; }; print f(1), f(b: 3, a: 2);", "[1, 2, []][2, 3, []]");
+        check("let [q, r] = divmod(17, 5); q, r = r, q; print q, r;", "23");
+        check(
+            "let f = func() { return 1; }; let g = func() { return 1; }; \
+             print contains([f], g), contains([f], f), index_of([f, g], g), length(group_by([f, g, f], func(x: func()) { return x; }));",
+            "falsetrue12",
+        );
+        check("let a = 7 // 2 + 1; a //= 2; print a, 5 // 2\n+ 1, 5\n// 2\n+ 1;", "236");
+        check("let x = 1; let f = func() { return x + y; }; let y = 2; print f();", "3");
+        check("println \"{} {x}\", 1;", "Error: println: Undefined variable: x");
+        check("println \"{\"; print \"}\"; print \"{x}\";", "{\n}{x}");
+    }
+
+    #[test]
+    fn return_outside_a_function() {
+        // Like the tree walker, a `return` outside a function or inside a loop only skips the rest of the statement it's in
+        check("return 1; print \"a\"; if true { return 2; print \"b\"; } print \"c\";", "ac");
+        check(
+            "let f = func() { let i = 0; while i < 3 { i += 1; if i == 2 { return 0; } print i; } return 9; }; print f();",
+            "1239",
+        );
+    }
+
+    #[test]
+    fn names_are_resolved_before_running() {
+        check("print 1; if false { print nope; }", "Error: Undefined variable: nope");
+        check("print 1; let f = func() { missing = 2; };", "Error: undeclared variable: missing");
+        check("print 1; print later; let later = 2;", "1Error: Undefined variable: later");
+        // Names declared anywhere might be a caller's, so they're only missed when they're used
+        check("let f = func() { return n; }; let g = func(n: num) { return f(); }; print g(3); print n;", "3Error: Undefined variable: n");
+        // Functions see the variables they were defined with as they were then, then their caller's
+        check("let f = func() { return y; }; let g = func() { let y = 5; return f(); }; print g();", "5");
+        check("let x = 1; let f = func() { return x; }; x = 2; let g = func() { let x = 3; return f(); }; print f(), g();", "11");
+        check("let f = func() { print x; let x = 2; print x; }; let x = 1; f();", "12");
+        // and assigning to one of them only changes the call's copy
+        check("let x = 1; let f = func() { x = 2; return x; }; print f(), x;", "21");
+        check(
+            "let count = func() { let n = 0; let inc = func() { n += 1; return n; }; inc(); return inc(); }; print count();",
+            "1",
+        );
+        check(
+            "let f = func(n: num) { let down = func(i: num) { if i == 0 { return n; } return down(i - 1); }; return down(3); }; print f(7);",
+            "7",
+        );
+    }
+
+    #[test]
+    fn shared_arrays_are_copied_on_write() {
+        check("let a = [1, 2]; let b = push(a, 3); let c = reverse(b); print a, b, c;", "[1, 2][1, 2, 3][3, 2, 1]");
+        check("let s = \"ab\"; let t = s; t += \"c\"; print s, t;", "ababc");
+        check("let a = [[1], 2]; let f = func(x: array) { return push(x, 3); }; print f(a), a[0];", "[[1], 2, 3][1]");
+    }
+
+    #[test]
+    fn hoisted_values_belong_to_one_run_of_a_loop() {
+        check("let n = 0; let out = 0; while n < 3 { let j = 0; while j < 2 { out += n * 10; j += 1; } n += 1; } print out;", "60");
+        check(
+            "let f = func(k: int) { let j = 0; let acc = 0; while j < 3 { acc += k * 10; if k > 0 && j == 1 { acc += f(k - 1); } j += 1; } return acc; }; print f(2);",
+            "90",
+        );
+    }
+
+    #[test]
+    fn calls_are_limited() {
+        let source = "let count = func(n: int) { if n == 0 { return 0; } return 1 + count(n - 1); }; \
+                      print count(40); print map([1], func(x: int) { return count(x + 60); });";
+        for &engine in &[Engine::Tree, Engine::Bytecode] {
+            let mut global_vars = global_vars(50);
+            assert_eq!(run_in(engine, &mut global_vars, source), "40Error: map: error in callback func(int): maximum call depth of 50 exceeded calling count");
+            global_vars.set_max_depth(1);
+            assert_eq!(
+                run_in(engine, &mut global_vars, "print map([1], func(x: int) { return map([x], func(y: int) { return y; }); });"),
+                "Error: map: error in callback func(int): map: error in callback func(int): maximum call depth of 1 exceeded calling an anonymous function"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_stop_scripts() {
+        let steps = Limits { max_steps: Some(10), ..Limits::default() };
+        let time = Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() };
+        let memory = Limits { max_memory: Some(10000), ..Limits::default() };
+        let length = Limits { max_length: Some(5), ..Limits::default() };
+        let stopped = |text: &str, limit| (text.to_string(), Some(limit));
+
+        for &engine in &[Engine::Tree, Engine::Bytecode] {
+            let count = "let i = 0; while true { i += 1; print i; }";
+            assert_eq!(run_limited(engine, &steps, count), stopped("12Error: step limit of 10 exceeded", Exceeded::Steps));
+            assert_eq!(run_limited(engine, &time, "while true { }"), stopped("Error: time limit of 0.02s exceeded", Exceeded::Time));
+            assert_eq!(
+                run_limited(engine, &memory, "let a = []; while true { a = push(a, 1); }"),
+                stopped("Error: memory limit of 10000 bytes exceeded", Exceeded::Memory)
+            );
+            let double = "let s = \"ab\"; while true { s += s; print length(s); }";
+            assert_eq!(run_limited(engine, &length, double), stopped("4Error: length limit of 5 exceeded", Exceeded::Length));
+            assert_eq!(
+                run_limited(engine, &length, "print repeat(\"ab\", 1000000000000);"),
+                stopped("Error: length limit of 5 exceeded", Exceeded::Length)
+            );
+            // Literals are made before the script starts, so they don't count, though anything made from them does
+            assert_eq!(run_limited(engine, &length, "print \"a long string\";"), ("a long string".to_string(), None));
+
+            // Work that takes a long time or a lot of memory in one step
+            let pow = "let b = 3; print b ** 3000000;";
+            assert_eq!(run_limited(engine, &time, pow), stopped("Error: time limit of 0.02s exceeded", Exceeded::Time));
+            assert_eq!(run_limited(engine, &memory, pow), stopped("Error: memory limit of 10000 bytes exceeded", Exceeded::Memory));
+            let grow = Limits { max_steps: Some(100000), ..memory.clone() };
+            assert_eq!(
+                run_limited(engine, &grow, "let b = 2 ** 64; while true { b *= 3; }"),
+                stopped("Error: memory limit of 10000 bytes exceeded", Exceeded::Memory)
+            );
+            // A hoisted expression that makes a string makes it every time, as far as the limits can tell
+            assert_eq!(
+                run_limited(engine, &grow, "let s = \"ab\"; while true { let t = s + s; }"),
+                stopped("Error: memory limit of 10000 bytes exceeded", Exceeded::Memory)
+            );
+            assert_eq!(run_limited(engine, &time, "print run(\"sleep\", [\"60\"]);"), stopped("Error: time limit of 0.02s exceeded", Exceeded::Time));
+            let exe = format!("print read_file({:?});", env::current_exe().unwrap().to_str().unwrap());
+            assert_eq!(run_limited(engine, &memory, &exe), stopped("Error: memory limit of 10000 bytes exceeded", Exceeded::Memory));
+        }
+    }
+
+    #[test]
+    fn errors_agree() {
+        check("let a = 1; if a == 1 { } elif 5 { }", "");
+        check("let a = 1; if a == 2 { } elif 5 { }", "Error: expected boolean, found bool");
+        check("while 1 { }", "Error: expected boolean, found int");
+        check("let eprintln = 1;", "Error: expected identifier, found keyword");
+        check("print 1; println length;", "Error: expected identifier, found keyword");
+        check("let f = func(x: num) { return x; }; f(\"s\");", "Error: wrong type of argument passed to function (expected num, found string)");
+        check("println sqrt(x: 1);", "Error: sqrt doesn't take named arguments (found x)");
+        check("const A = 1; let f = func() { A = 2; }; f();", "Error: cannot assign to constant A (declared on line 1)");
+        check("let x = 5; x();", "Error: cannot call int as function");
+        check("remove(\"/\", 0);", "Error: remove: expected array, found string");
+        check(
+            "let f = func() { return map([1], func(x: num) { return x + \"a\"; }); }; f();",
+            "Error: map: error in callback func(num): invalid operation (int with string)",
+        );
+    }
+}